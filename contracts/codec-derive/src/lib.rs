@@ -0,0 +1,181 @@
+/**
+ * Derive macros for the `elrond_codec::Encode` and `elrond_codec::Decode` traits
+ *
+ * Structs are encoded as the nested encoding of each field in declaration order.
+ * Enums must only have unit variants and are encoded as a single u8 holding the index of the variant
+ * in declaration order. Decoding any other value fails with `DecodeError::InvalidValue`. Like any other
+ * number, an empty top-level value (e.g. unset storage) decodes as discriminant 0, the first variant.
+ *
+ * This is exactly the layout the hand-written implementations used, so hashes and storage are unaffected.
+ *
+ * example:
+ *
+ * ```ignore
+ * #[derive(Encode, Decode)]
+ * pub struct Trade<BigUint: BigUintApi> {
+ *     pub filled_price: BigUint,
+ *     pub timestamp: u64,
+ * }
+ * ```
+ */
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident};
+
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let body = match &ast.data {
+        Data::Struct(data) => {
+            let fields = match &data.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let field_name = &field.ident;
+                        quote! { elrond_codec::Encode::dep_encode_to(&self.#field_name, dest)?; }
+                    })
+                    .collect::<Vec<_>>(),
+                _ => return unsupported(name, "only structs with named fields are supported"),
+            };
+            quote! {
+                #(#fields)*
+                core::result::Result::Ok(())
+            }
+        }
+        Data::Enum(data) => {
+            let variants = match unit_variants(data) {
+                Ok(variants) => variants,
+                Err(error) => return error,
+            };
+            let arms = variants.iter().enumerate().map(|(index, variant)| {
+                let discriminant = index as u8;
+                quote! { #name::#variant => #discriminant, }
+            });
+            quote! {
+                let discriminant: u8 = match self {
+                    #(#arms)*
+                };
+                elrond_codec::Encode::dep_encode_to(&discriminant, dest)
+            }
+        }
+        Data::Union(_) => return unsupported(name, "unions are not supported"),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics elrond_codec::Encode for #name #ty_generics #where_clause {
+            fn dep_encode_to<O: elrond_codec::Output>(
+                &self,
+                dest: &mut O,
+            ) -> core::result::Result<(), elrond_codec::EncodeError> {
+                #body
+            }
+        }
+    };
+    expanded.into()
+}
+
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let mut top_decode = TokenStream2::new();
+    let body = match &ast.data {
+        Data::Struct(data) => {
+            let fields = match &data.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let field_name = &field.ident;
+                        let field_type = &field.ty;
+                        quote! { #field_name: <#field_type as elrond_codec::Decode>::dep_decode(input)?, }
+                    })
+                    .collect::<Vec<_>>(),
+                _ => return unsupported(name, "only structs with named fields are supported"),
+            };
+            quote! {
+                core::result::Result::Ok(#name {
+                    #(#fields)*
+                })
+            }
+        }
+        Data::Enum(data) => {
+            let variants = match unit_variants(data) {
+                Ok(variants) => variants,
+                Err(error) => return error,
+            };
+            let arms = variants
+                .iter()
+                .enumerate()
+                .map(|(index, variant)| {
+                    let discriminant = index as u8;
+                    quote! { #discriminant => core::result::Result::Ok(#name::#variant), }
+                })
+                .collect::<Vec<_>>();
+            top_decode = quote! {
+                fn top_decode<I: elrond_codec::Input>(
+                    input: &mut I,
+                ) -> core::result::Result<Self, elrond_codec::DecodeError> {
+                    match <u8 as elrond_codec::Decode>::top_decode(input)? {
+                        #(#arms)*
+                        _ => core::result::Result::Err(elrond_codec::DecodeError::InvalidValue),
+                    }
+                }
+            };
+            quote! {
+                match <u8 as elrond_codec::Decode>::dep_decode(input)? {
+                    #(#arms)*
+                    _ => core::result::Result::Err(elrond_codec::DecodeError::InvalidValue),
+                }
+            }
+        }
+        Data::Union(_) => return unsupported(name, "unions are not supported"),
+    };
+
+    let expanded = quote! {
+        impl #impl_generics elrond_codec::Decode for #name #ty_generics #where_clause {
+            #top_decode
+
+            fn dep_decode<I: elrond_codec::Input>(
+                input: &mut I,
+            ) -> core::result::Result<Self, elrond_codec::DecodeError> {
+                #body
+            }
+        }
+    };
+    expanded.into()
+}
+
+// the discriminant is a u8 so at most 256 unit variants can be encoded
+fn unit_variants(data: &syn::DataEnum) -> Result<Vec<&Ident>, TokenStream> {
+    let mut variants = Vec::new();
+    for variant in data.variants.iter() {
+        match variant.fields {
+            Fields::Unit => variants.push(&variant.ident),
+            _ => {
+                return Err(unsupported(
+                    &variant.ident,
+                    "only enums with unit variants are supported",
+                ))
+            }
+        }
+    }
+    if variants.len() > 256 {
+        return Err(unsupported(&data.variants[256].ident, "at most 256 variants are supported"));
+    }
+    Ok(variants)
+}
+
+fn unsupported(span: &Ident, message: &str) -> TokenStream {
+    let error: TokenStream2 = syn::Error::new(span.span(), message).to_compile_error();
+    error.into()
+}
@@ -0,0 +1,326 @@
+/**
+ * Checks the derived encodings against the bytes and hashes of the hand-written implementations they replaced.
+ *
+ * The generated code only names `elrond_codec` paths, so the types below stand in for it. They encode the
+ * primitives the way elrond_codec does: u8 as one byte, u64 as 8 big-endian bytes, addresses and hashes as
+ * their 32 bytes and big numbers as a u32 length followed by their big-endian bytes. An empty top-level u8
+ * decodes as 0, like any other number.
+ */
+use codec_derive::{Decode, Encode};
+use tiny_keccak::{Hasher, Keccak};
+
+mod elrond_codec {
+    #[derive(Debug, PartialEq)]
+    pub enum EncodeError {}
+
+    #[derive(Debug, PartialEq)]
+    pub enum DecodeError {
+        InputTooShort,
+        InputTooLong,
+        InvalidValue,
+    }
+
+    pub trait Output {
+        fn write(&mut self, bytes: &[u8]);
+    }
+
+    impl Output for Vec<u8> {
+        fn write(&mut self, bytes: &[u8]) {
+            self.extend_from_slice(bytes);
+        }
+    }
+
+    pub trait Input {
+        fn remaining_len(&self) -> usize;
+
+        fn read_slice(&mut self, length: usize) -> Result<&[u8], DecodeError>;
+    }
+
+    impl<'a> Input for &'a [u8] {
+        fn remaining_len(&self) -> usize {
+            self.len()
+        }
+
+        fn read_slice(&mut self, length: usize) -> Result<&[u8], DecodeError> {
+            let bytes: &'a [u8] = self;
+            if length > bytes.len() {
+                return Err(DecodeError::InputTooShort);
+            }
+            let (head, tail) = bytes.split_at(length);
+            *self = tail;
+            Ok(head)
+        }
+    }
+
+    pub trait Encode: Sized {
+        fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError>;
+
+        fn top_encode(&self) -> Result<Vec<u8>, EncodeError> {
+            let mut bytes = Vec::new();
+            self.dep_encode_to(&mut bytes)?;
+            Ok(bytes)
+        }
+    }
+
+    pub trait Decode: Sized {
+        fn top_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
+            let value = Self::dep_decode(input)?;
+            if input.remaining_len() > 0 {
+                return Err(DecodeError::InputTooLong);
+            }
+            Ok(value)
+        }
+
+        fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError>;
+    }
+
+    impl Encode for u8 {
+        fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+            dest.write(&[*self]);
+            Ok(())
+        }
+    }
+
+    impl Decode for u8 {
+        fn top_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
+            match input.remaining_len() {
+                0 => Ok(0),
+                1 => Self::dep_decode(input),
+                _ => Err(DecodeError::InputTooLong),
+            }
+        }
+
+        fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
+            Ok(input.read_slice(1)?[0])
+        }
+    }
+
+    impl Encode for u64 {
+        fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+            dest.write(&self.to_be_bytes());
+            Ok(())
+        }
+    }
+
+    impl Decode for u64 {
+        fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(input.read_slice(8)?);
+            Ok(u64::from_be_bytes(bytes))
+        }
+    }
+}
+
+use elrond_codec::{Decode, DecodeError, Encode, EncodeError, Input, Output};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct H256([u8; 32]);
+
+pub type Address = H256;
+
+impl H256 {
+    fn from_str(name: &str) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(name.as_bytes());
+        H256(bytes)
+    }
+}
+
+impl Encode for H256 {
+    fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+        dest.write(&self.0);
+        Ok(())
+    }
+}
+
+impl Decode for H256 {
+    fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(input.read_slice(32)?);
+        Ok(H256(bytes))
+    }
+}
+
+pub trait BigUintApi: Encode + Decode {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BigUint(Vec<u8>);
+
+impl BigUintApi for BigUint {}
+
+impl Encode for BigUint {
+    fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+        dest.write(&(self.0.len() as u32).to_be_bytes());
+        dest.write(&self.0);
+        Ok(())
+    }
+}
+
+impl Decode for BigUint {
+    fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
+        let mut length = [0u8; 4];
+        length.copy_from_slice(input.read_slice(4)?);
+        let bytes = input.read_slice(u32::from_be_bytes(length) as usize)?;
+        Ok(BigUint(bytes.to_vec()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    PartiallyCancelled,
+    Cancelled,
+}
+
+// the order layout of the hand-written implementation, kept by the exchange as `LegacyOrder`
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct Order<BigUint: BigUintApi> {
+    pub sender_address: Address,
+    pub matcher_address: Address,
+    pub base_asset: Address,
+    pub quote_asset: Address,
+    pub matcher_fee_asset: Address,
+    pub amount: BigUint,
+    pub price: BigUint,
+    pub matcher_fee: BigUint,
+    pub nonce: BigUint,
+    pub expiration: u64,
+    pub side: OrderSide,
+    pub signature: H256,
+}
+
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct Trade<BigUint: BigUintApi> {
+    pub filled_price: BigUint,
+    pub filled_amount: BigUint,
+    pub fee_paid: BigUint,
+    pub timestamp: u64,
+}
+
+fn keccak256(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    let mut hash = [0u8; 32];
+    hasher.update(bytes);
+    hasher.finalize(&mut hash);
+    hash
+}
+
+fn hex(value: &str) -> Vec<u8> {
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+// the partially filled order of test/upgrade/upgrade-from-unversioned.scen.json
+fn legacy_order() -> Order<BigUint> {
+    Order {
+        sender_address: Address::from_str("agent_b_address_______________s1"),
+        matcher_address: Address::from_str("matcher_address_______________s1"),
+        base_asset: Address::from_str("wbtc_contract_________________s1"),
+        quote_asset: H256([0u8; 32]),
+        matcher_fee_asset: H256([0u8; 32]),
+        amount: BigUint(vec![0x64]),
+        price: BigUint(vec![0x02]),
+        matcher_fee: BigUint(vec![0x0a]),
+        nonce: BigUint(vec![0x01]),
+        expiration: 2000,
+        side: OrderSide::Buy,
+        signature: H256([0x11; 32]),
+    }
+}
+
+#[test]
+fn order_encodes_as_the_hand_written_layout() {
+    let order = legacy_order();
+    let mut expected = Vec::new();
+    expected.extend_from_slice(b"agent_b_address_______________s1");
+    expected.extend_from_slice(b"matcher_address_______________s1");
+    expected.extend_from_slice(b"wbtc_contract_________________s1");
+    expected.extend_from_slice(&[0u8; 64]);
+    expected.extend_from_slice(&hex(
+        "00000001640000000102000000010a000000010100000000000007d000",
+    ));
+    expected.extend_from_slice(&[0x11; 32]);
+
+    let bytes = order.top_encode().unwrap();
+    assert_eq!(bytes, expected);
+    assert_eq!(
+        keccak256(&bytes).to_vec(),
+        hex("835fcbbcb8c4e060696aca74f0f777f35e0c91d2e168b6bb8fd581d56a085e6f")
+    );
+    assert_eq!(Order::<BigUint>::top_decode(&mut &bytes[..]), Ok(order));
+}
+
+#[test]
+fn trade_encodes_as_the_hand_written_layout() {
+    // the trade of the same order in the upgrade scenario
+    let trade = Trade {
+        filled_price: BigUint(vec![0x02]),
+        filled_amount: BigUint(vec![0x28]),
+        fee_paid: BigUint(vec![0x04]),
+        timestamp: 500,
+    };
+    let expected = hex("00000001020000000128000000010400000000000001f4");
+
+    assert_eq!(trade.top_encode().unwrap(), expected);
+    assert_eq!(Trade::<BigUint>::top_decode(&mut &expected[..]), Ok(trade));
+}
+
+#[test]
+fn enum_variants_encode_as_their_index() {
+    let statuses = [
+        OrderStatus::New,
+        OrderStatus::PartiallyFilled,
+        OrderStatus::Filled,
+        OrderStatus::PartiallyCancelled,
+        OrderStatus::Cancelled,
+    ];
+    for (index, status) in statuses.iter().enumerate() {
+        let bytes = status.top_encode().unwrap();
+        assert_eq!(bytes, vec![index as u8]);
+        assert_eq!(
+            OrderStatus::top_decode(&mut &bytes[..]).as_ref(),
+            Ok(status)
+        );
+    }
+    assert_eq!(OrderSide::Sell.top_encode().unwrap(), vec![1]);
+}
+
+#[test]
+fn out_of_range_discriminant_is_invalid() {
+    assert_eq!(
+        OrderStatus::top_decode(&mut &[5u8][..]),
+        Err(DecodeError::InvalidValue)
+    );
+    assert_eq!(
+        OrderSide::dep_decode(&mut &[2u8][..]),
+        Err(DecodeError::InvalidValue)
+    );
+
+    let mut bytes = legacy_order().top_encode().unwrap();
+    // the side is the byte before the signature
+    let side = bytes.len() - 33;
+    bytes[side] = 2;
+    assert_eq!(
+        Order::<BigUint>::top_decode(&mut &bytes[..]),
+        Err(DecodeError::InvalidValue)
+    );
+}
+
+#[test]
+fn empty_storage_decodes_as_the_first_variant() {
+    assert_eq!(OrderStatus::top_decode(&mut &[][..]), Ok(OrderStatus::New));
+    assert_eq!(
+        OrderSide::dep_decode(&mut &[][..]),
+        Err(DecodeError::InputTooShort)
+    );
+}
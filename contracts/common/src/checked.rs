@@ -0,0 +1,87 @@
+/**
+ * Checked arithmetic for BigUint (or any integer type) that returns an `SCResult` instead of aborting,
+ * so the caller decides on the error. Combine with `sc_try!` to propagate it.
+ *
+ * example:
+ *
+ * ```ignore
+ * *balance = sc_try!(checked_sub!(*balance, *amount, ErrorCode::InsufficientBalance));
+ * let fee = sc_try!(checked_div!(fee * filled_amount, order.amount));
+ * ```
+ */
+#[macro_export]
+macro_rules! checked_sub {
+	($left:expr, $right:expr) => {
+		$crate::checked_sub!($left, $right, $crate::ErrorCode::Underflow)
+	};
+	($left:expr, $right:expr, $error_msg:expr) => {
+		match (&$left, &$right) {
+			(left, right) => {
+				if *left >= *right {
+					elrond_wasm::SCResult::Ok(left - right)
+				} else {
+					sc_error!($crate::ErrorMessage::error_message(&$error_msg))
+				}
+			}
+		}
+	};
+}
+
+#[macro_export]
+macro_rules! checked_div {
+	($left:expr, $right:expr) => {
+		$crate::checked_div!($left, $right, $crate::ErrorCode::DivisionByZero)
+	};
+	($left:expr, $right:expr, $error_msg:expr) => {
+		match (&$left, &$right) {
+			(left, right) => {
+				if *right > 0 {
+					elrond_wasm::SCResult::Ok(left / right)
+				} else {
+					sc_error!($crate::ErrorMessage::error_message(&$error_msg))
+				}
+			}
+		}
+	};
+}
+
+/**
+ * Which way `mul_div!` rounds when the division isn't exact
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rounding {
+	Down,
+	Up,
+}
+
+/**
+ * Computes `$a * $b / $c` with the given `Rounding`, returning an `SCResult`. The multiplication is done
+ * first so no precision is lost, which is what pro-rata calculations like fees need.
+ *
+ * example:
+ *
+ * ```ignore
+ * let fee = sc_try!(mul_div!(order.matcher_fee, filled_amount, order.amount, Rounding::Down));
+ * ```
+ */
+#[macro_export]
+macro_rules! mul_div {
+	($a:expr, $b:expr, $c:expr, $rounding:expr) => {
+		match (&$a, &$b, &$c) {
+			(a, b, c) => {
+				if *c > 0 {
+					let product = a * b;
+					let quotient = &product / c;
+					if $rounding == $crate::Rounding::Up && &quotient * c < product {
+						// (q * c + r + c) / c == q + 1 for any remainder 0 < r < c
+						elrond_wasm::SCResult::Ok(&(&product + c) / c)
+					} else {
+						elrond_wasm::SCResult::Ok(quotient)
+					}
+				} else {
+					sc_error!($crate::ErrorMessage::error_message(&$crate::ErrorCode::DivisionByZero))
+				}
+			}
+		}
+	};
+}
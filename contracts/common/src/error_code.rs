@@ -0,0 +1,107 @@
+macro_rules! error_codes {
+	($(#[$attr:meta])* $($name:ident = $code:literal, $message:literal;)*) => {
+		$(#[$attr])*
+		#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+		pub enum ErrorCode {
+			$($name,)*
+		}
+
+		impl ErrorCode {
+			pub fn code(&self) -> u32 {
+				match self {
+					$(ErrorCode::$name => $code,)*
+				}
+			}
+		}
+
+		impl crate::ErrorMessage for ErrorCode {
+			fn error_message(&self) -> &'static str {
+				match self {
+					$(ErrorCode::$name => concat!("E", stringify!($code), ": ", $message),)*
+				}
+			}
+		}
+	};
+}
+
+error_codes! {
+	/**
+	 * Errors shared by the contracts, each with a stable numeric code.
+	 *
+	 * The message returned on chain is always "E<code>: <message>" so that off-chain clients can map
+	 * a failed transaction back to the error. Codes are never reused or renumbered, see the table in
+	 * contracts/exchange/README.md when adding one.
+	 */
+	WrongMatcher = 1, "caller is not the matcher of the order";
+	AssetMismatch = 2, "orders are not for the same asset pair";
+	Overfill = 3, "fill exceeds the remaining order amount";
+	OrderCancelled = 4, "order is cancelled";
+	PriceOutOfRange = 5, "fill price is outside the order limit";
+	OrderExpired = 6, "order has expired";
+	FeeOverCap = 7, "fee exceeds the order matcher fee";
+	InvalidSignature = 8, "invalid signature";
+	InsufficientBalance = 9, "insufficient balance";
+	ExchangePaused = 10, "exchange is paused";
+	AuthorizationExpired = 11, "authorization has expired";
+	NonceAlreadyUsed = 12, "nonce already used";
+	NotOrderOwner = 13, "caller is not the owner of the order";
+	WrongCaller = 14, "caller is not allowed to call this endpoint";
+	ZeroValue = 15, "value must not be zero";
+	Underflow = 16, "subtraction would be negative";
+	DivisionByZero = 17, "division by zero";
+	SelfTrade = 18, "orders have the same sender";
+	SideMismatch = 19, "orders must be one buy and one sell";
+	OrderNotTriggered = 20, "order trigger price has not been reached";
+	InsufficientObservations = 21, "no price observation old enough for the window";
+	UnhealthyPosition = 22, "margin position is not healthy";
+	NotLiquidatable = 23, "position is not negative or overdue";
+	LiquidationTooLarge = 24, "amount exceeds the liability";
+	InsufficientCollateral = 25, "not enough collateral to reimburse the liquidator";
+	LiquidationNotImproving = 26, "liquidation does not improve the position";
+	UnknownPriceProvider = 27, "not an authorized price provider";
+	InvalidPriceTimestamp = 28, "price timestamp is stale, in the future or not newer than the last one";
+	NoFreshPrice = 29, "no fresh price for the asset";
+	FeeAssetNotAccepted = 30, "fee asset is not accepted";
+	FeeTooLow = 31, "matcher fee is below the required fee";
+	WithdrawalLimitExceeded = 32, "withdrawal exceeds the daily limit";
+	DepositCapExceeded = 33, "deposit exceeds the deposit cap";
+	DepositsDisabled = 34, "asset does not accept deposits";
+	WithdrawalsDisabled = 35, "asset does not allow withdrawals";
+	AssetNotTradable = 36, "asset is not tradable";
+	Insolvent = 37, "holdings do not cover the user balances";
+	UnauthorizedSigner = 38, "order signer is not the sender or one of their delegates allowed to trade";
+	NotOwner = 39, "must be called by the owner";
+	NotUserOrGuardian = 40, "must be called by the user or the guardian";
+	ForcedWithdrawalPending = 41, "forced withdrawal already pending";
+	NoForcedWithdrawal = 42, "no forced withdrawal pending";
+	ForcedWithdrawalNotAvailable = 43, "forced withdrawal not yet available";
+	QueuedWithdrawalPending = 44, "queued withdrawal already pending";
+	NoQueuedWithdrawal = 45, "no queued withdrawal pending";
+	QueuedWithdrawalNotAvailable = 46, "queued withdrawal not yet available";
+	NoUnstake = 47, "no unstake pending";
+	UnstakeNotAvailable = 48, "unstake not yet available";
+	StakingNotEnabled = 49, "staking is not enabled";
+	StakingAssetInUse = 50, "cannot change the staking asset while stakes exist";
+	TiersNotSorted = 51, "tiers must be sorted by stake";
+	DelayOverflow = 52, "delay overflows the timestamp";
+	DelayAboveMaximum = 53, "delay above the maximum";
+	AboveBasisPoints = 54, "value above 100%";
+	LengthMismatch = 55, "argument lists must have the same length";
+	UserBalanceTotalsIncomplete = 56, "user balance totals incomplete";
+	UserBalanceTotalsComplete = 57, "user balance totals already complete";
+	NoExcess = 58, "no excess to rescue";
+	NoBrokerFees = 59, "no broker fees to claim";
+	SelfDelegation = 60, "cannot delegate to self";
+	ExpirationInPast = 61, "expiration is in the past";
+	DelegateNotRegistered = 62, "delegate not registered";
+	AuthorizationMismatch = 63, "authorization does not match the order";
+	AssetNotListed = 64, "asset not listed";
+	StorageUpToDate = 65, "storage already up to date";
+	NoMigration = 66, "no migration registered for the storage version";
+	NoLegacyMigration = 67, "no legacy migration open";
+	EncodingFailed = 68, "message could not be encoded";
+	TokenCallFailed = 69, "token contract call failed";
+	ExchangeNotPaused = 70, "exchange is not paused";
+	UserBalanceTotalsSeeding = 71, "user balance totals are being seeded";
+	LegacyMigrationOpen = 72, "legacy migration still open";
+}
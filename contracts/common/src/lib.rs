@@ -1,24 +1,159 @@
 #![no_std]
+
+extern crate alloc;
+
+mod checked;
+mod error_code;
+
+pub use checked::Rounding;
+pub use error_code::ErrorCode;
+
+use alloc::vec::Vec;
+
 /**
  * Super hand macro, allow us to write Solidity style require!(<condition>, <error_msg>) and avoid if statements
  * 
  * It can only be used in a function that returns `Result<_, SCError>` where _ can be any type
  * 
+ * The error can be a string or an `ErrorCode`
+ * 
  * example:
  * 
- * ```
+ * ```ignore
  * fn only_callable_by_owner(&self) -> Result<(), SCError> {
  *     require!(self.get_caller() == self.get_owner(), "Caller must be owner");
+ *     require!(order.expiration >= self.get_block_timestamp(), ErrorCode::OrderExpired);
  *     Ok(())
  * }
  * ```
  */
-
 #[macro_export]
 macro_rules! require {
 	($expression:expr, $error_msg:expr) => {
 		if (($expression) == false) {
-			return sc_error!($error_msg)
+			return sc_error!($crate::ErrorMessage::error_message(&$error_msg))
+		}
+	};
+}
+
+/**
+ * Like `require!` but compares two values and includes both of them, top-encoded as hex, in the error:
+ * "<error_msg> (left: 0x.., right: 0x..)"
+ *
+ * The values must implement `Encode` and that trait must be in scope, which `imports!()` takes care of
+ *
+ * example:
+ *
+ * ```ignore
+ * require_le!(amount, balance, ErrorCode::InsufficientBalance);
+ * ```
+ */
+#[macro_export]
+macro_rules! require_eq {
+	($left:expr, $right:expr, $error_msg:expr) => {
+		$crate::require_cmp!($left, ==, $right, $error_msg)
+	};
+}
+
+#[macro_export]
+macro_rules! require_ne {
+	($left:expr, $right:expr, $error_msg:expr) => {
+		$crate::require_cmp!($left, !=, $right, $error_msg)
+	};
+}
+
+#[macro_export]
+macro_rules! require_le {
+	($left:expr, $right:expr, $error_msg:expr) => {
+		$crate::require_cmp!($left, <=, $right, $error_msg)
+	};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! require_cmp {
+	($left:expr, $op:tt, $right:expr, $error_msg:expr) => {
+		match (&$left, &$right) {
+			(left, right) => {
+				if !(*left $op *right) {
+					return elrond_wasm::SCResult::Err(elrond_wasm::SCError::Dynamic(
+						$crate::comparison_error_message(
+							$crate::ErrorMessage::error_message(&$error_msg),
+							left.top_encode().unwrap_or_default().as_slice(),
+							right.top_encode().unwrap_or_default().as_slice(),
+						),
+					));
+				}
+			}
 		}
 	};
 }
+
+/**
+ * Fails unless the caller of the endpoint is `$address`, with the caller and `$address` in the error like `require_eq!`
+ *
+ * `self` has to be passed in explicitly as macros cannot refer to it
+ *
+ * example:
+ *
+ * ```ignore
+ * ensure_caller!(self, self.get_owner());
+ * ```
+ */
+#[macro_export]
+macro_rules! ensure_caller {
+	($self:ident, $address:expr) => {
+		$crate::ensure_caller!($self, $address, $crate::ErrorCode::WrongCaller)
+	};
+	($self:ident, $address:expr, $error_msg:expr) => {
+		$crate::require_eq!($self.get_caller(), $address, $error_msg)
+	};
+}
+
+/**
+ * Fails if `$value` (a BigUint or an integer) is zero
+ */
+#[macro_export]
+macro_rules! ensure_not_zero {
+	($value:expr) => {
+		$crate::ensure_not_zero!($value, $crate::ErrorCode::ZeroValue)
+	};
+	($value:expr, $error_msg:expr) => {
+		$crate::require!($value > 0, $error_msg)
+	};
+}
+
+/**
+ * Anything that can be passed as the error of `require!`
+ */
+pub trait ErrorMessage {
+	fn error_message(&self) -> &'static str;
+}
+
+impl ErrorMessage for &'static str {
+	fn error_message(&self) -> &'static str {
+		self
+	}
+}
+
+/**
+ * Builds the message used by `require_eq!` and friends
+ */
+pub fn comparison_error_message(error_msg: &str, left: &[u8], right: &[u8]) -> Vec<u8> {
+	let mut message = Vec::with_capacity(error_msg.len() + 2 * (left.len() + right.len()) + 24);
+	message.extend_from_slice(error_msg.as_bytes());
+	message.extend_from_slice(b" (left: 0x");
+	push_hex(&mut message, left);
+	message.extend_from_slice(b", right: 0x");
+	push_hex(&mut message, right);
+	message.push(b')');
+	message
+}
+
+fn push_hex(dest: &mut Vec<u8>, bytes: &[u8]) {
+	const DIGITS: &[u8; 16] = b"0123456789abcdef";
+	for byte in bytes {
+		dest.push(DIGITS[(byte >> 4) as usize]);
+		dest.push(DIGITS[(byte & 0x0f) as usize]);
+	}
+}
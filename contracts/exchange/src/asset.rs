@@ -0,0 +1,37 @@
+use codec_derive::{Decode, Encode};
+
+imports!();
+
+/**
+ * What users can do with a listed asset
+ */
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub enum AssetStatus {
+    Active,
+    DepositOnly,
+    WithdrawOnly,
+    Delisted,
+}
+
+impl AssetStatus {
+    pub fn allows_deposits(&self) -> bool {
+        *self == AssetStatus::Active || *self == AssetStatus::DepositOnly
+    }
+
+    // users can always take a delisted asset out of the exchange
+    pub fn allows_withdrawals(&self) -> bool {
+        *self != AssetStatus::DepositOnly
+    }
+
+    pub fn allows_trading(&self) -> bool {
+        *self == AssetStatus::Active
+    }
+}
+
+#[derive(Encode, Decode)]
+pub struct AssetInfo {
+    pub asset_address: Address,
+    pub ticker: Vec<u8>,
+    pub decimals: u8,
+    pub status: AssetStatus,
+}
@@ -0,0 +1,161 @@
+use codec_derive::{Decode, Encode};
+use elrond_codec::*;
+
+imports!();
+
+static WITHDRAWAL_TAG: u8 = 0;
+static CANCELLATION_TAG: u8 = 1;
+static INTERNAL_TRANSFER_TAG: u8 = 2;
+
+/**
+ * A message signed off-chain by its signer so that any account can relay it to the exchange.
+ *
+ * The signed payload is the exchange address, a tag identifying the message type and every field
+ * except the signature. It is hashed with keccak256 and the hash is signed with the ed25519 key
+ * behind the signer's address.
+ */
+pub trait SignedMessage {
+    fn signer(&self) -> &Address;
+    fn nonce(&self) -> u64;
+    fn expiration(&self) -> u64;
+    fn signature(&self) -> &[u8];
+    fn signed_payload(&self, exchange_address: &Address) -> Result<Vec<u8>, EncodeError>;
+}
+
+#[derive(Encode, Decode)]
+pub struct WithdrawalAuthorization<BigUint: BigUintApi> {
+    pub sender_address: Address,
+    pub asset_address: Address,
+    pub amount: BigUint,
+    pub nonce: u64,
+    pub expiration: u64,
+    pub signature: Vec<u8>,
+}
+
+impl<BigUint: BigUintApi> WithdrawalAuthorization<BigUint> {
+    fn dep_encode_unsigned<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+        self.sender_address.dep_encode_to(dest)?;
+        self.asset_address.dep_encode_to(dest)?;
+        self.amount.dep_encode_to(dest)?;
+        self.nonce.dep_encode_to(dest)?;
+        self.expiration.dep_encode_to(dest)
+    }
+}
+
+impl<BigUint: BigUintApi> SignedMessage for WithdrawalAuthorization<BigUint> {
+    fn signer(&self) -> &Address {
+        &self.sender_address
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn expiration(&self) -> u64 {
+        self.expiration
+    }
+
+    fn signature(&self) -> &[u8] {
+        self.signature.as_slice()
+    }
+
+    fn signed_payload(&self, exchange_address: &Address) -> Result<Vec<u8>, EncodeError> {
+        let mut payload = Vec::new();
+        exchange_address.dep_encode_to(&mut payload)?;
+        WITHDRAWAL_TAG.dep_encode_to(&mut payload)?;
+        self.dep_encode_unsigned(&mut payload)?;
+        Result::Ok(payload)
+    }
+}
+
+#[derive(Encode, Decode)]
+pub struct CancellationAuthorization {
+    pub sender_address: Address,
+    pub order_hash: H256,
+    pub nonce: u64,
+    pub expiration: u64,
+    pub signature: Vec<u8>,
+}
+
+impl CancellationAuthorization {
+    fn dep_encode_unsigned<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+        self.sender_address.dep_encode_to(dest)?;
+        self.order_hash.dep_encode_to(dest)?;
+        self.nonce.dep_encode_to(dest)?;
+        self.expiration.dep_encode_to(dest)
+    }
+}
+
+impl SignedMessage for CancellationAuthorization {
+    fn signer(&self) -> &Address {
+        &self.sender_address
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn expiration(&self) -> u64 {
+        self.expiration
+    }
+
+    fn signature(&self) -> &[u8] {
+        self.signature.as_slice()
+    }
+
+    fn signed_payload(&self, exchange_address: &Address) -> Result<Vec<u8>, EncodeError> {
+        let mut payload = Vec::new();
+        exchange_address.dep_encode_to(&mut payload)?;
+        CANCELLATION_TAG.dep_encode_to(&mut payload)?;
+        self.dep_encode_unsigned(&mut payload)?;
+        Result::Ok(payload)
+    }
+}
+
+#[derive(Encode, Decode)]
+pub struct InternalTransferAuthorization<BigUint: BigUintApi> {
+    pub sender_address: Address,
+    pub asset_address: Address,
+    pub to: Address,
+    pub amount: BigUint,
+    pub nonce: u64,
+    pub expiration: u64,
+    pub signature: Vec<u8>,
+}
+
+impl<BigUint: BigUintApi> InternalTransferAuthorization<BigUint> {
+    fn dep_encode_unsigned<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+        self.sender_address.dep_encode_to(dest)?;
+        self.asset_address.dep_encode_to(dest)?;
+        self.to.dep_encode_to(dest)?;
+        self.amount.dep_encode_to(dest)?;
+        self.nonce.dep_encode_to(dest)?;
+        self.expiration.dep_encode_to(dest)
+    }
+}
+
+impl<BigUint: BigUintApi> SignedMessage for InternalTransferAuthorization<BigUint> {
+    fn signer(&self) -> &Address {
+        &self.sender_address
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    fn expiration(&self) -> u64 {
+        self.expiration
+    }
+
+    fn signature(&self) -> &[u8] {
+        self.signature.as_slice()
+    }
+
+    fn signed_payload(&self, exchange_address: &Address) -> Result<Vec<u8>, EncodeError> {
+        let mut payload = Vec::new();
+        exchange_address.dep_encode_to(&mut payload)?;
+        INTERNAL_TRANSFER_TAG.dep_encode_to(&mut payload)?;
+        self.dep_encode_unsigned(&mut payload)?;
+        Result::Ok(payload)
+    }
+}
@@ -0,0 +1,31 @@
+use codec_derive::{Decode, Encode};
+
+/**
+ * What a delegate key may do for the user that registered it. No delegate can ever withdraw or transfer funds.
+ */
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub enum DelegatePermissions {
+    Trade,
+    TradeAndCancel,
+    Cancel,
+}
+
+impl DelegatePermissions {
+    pub fn can_trade(&self) -> bool {
+        *self != DelegatePermissions::Cancel
+    }
+
+    pub fn can_cancel(&self) -> bool {
+        *self != DelegatePermissions::Trade
+    }
+}
+
+/**
+ * A key registered by a user to act for them, active until `expiration`. An expiration of 0 means no delegate
+ * is registered.
+ */
+#[derive(Encode, Decode)]
+pub struct Delegate {
+    pub expiration: u64,
+    pub permissions: DelegatePermissions,
+}
@@ -1,6 +1,8 @@
 imports!();
 
+use crate::delegate::Delegate;
 use crate::order_status::OrderStatus;
+use crate::self_trade::SelfTradePrevention;
 
 #[elrond_wasm_derive::module(EventsModuleImpl)]
 pub trait EventsModule {
@@ -30,4 +32,91 @@ pub trait EventsModule {
     #[event("0x0000000000000000000000000000000000000000000000000000000000000004")]
     fn order_update(&self, order_hash: &H256, user: &Address, status: &OrderStatus);
     // cannot use Bytes32 in event. Bug?
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000005")]
+    fn new_internal_transfer(
+        &self,
+        from: &Address,
+        to: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    );
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000006")]
+    fn forced_withdrawal_requested(
+        &self,
+        user_address: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    );
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000007")]
+    fn forced_withdrawal_executed(
+        &self,
+        user_address: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    );
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000008")]
+    fn self_trade_prevented(
+        &self,
+        user_address: &Address,
+        cancelled_order_hash: &H256,
+        mode: &SelfTradePrevention,
+    );
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000009")]
+    fn position_liquidated(
+        &self,
+        broker: &Address,
+        liquidator: &Address,
+        redeem_asset: &Address,
+        amount: &BigUint,
+    );
+
+    #[event("0x000000000000000000000000000000000000000000000000000000000000000a")]
+    fn collateral_seized(
+        &self,
+        broker: &Address,
+        liquidator: &Address,
+        collateral_asset: &Address,
+        amount: &BigUint,
+    );
+
+    #[event("0x000000000000000000000000000000000000000000000000000000000000000b")]
+    fn price_submitted(&self, provider_address: &Address, asset_address: &Address, price: &BigUint);
+
+    #[event("0x000000000000000000000000000000000000000000000000000000000000000c")]
+    fn staked(&self, user_address: &Address, amount: &BigUint);
+
+    #[event("0x000000000000000000000000000000000000000000000000000000000000000d")]
+    fn unstake_requested(&self, user_address: &Address, amount: &BigUint);
+
+    #[event("0x000000000000000000000000000000000000000000000000000000000000000e")]
+    fn stake_withdrawn(&self, user_address: &Address, amount: &BigUint);
+
+    #[event("0x000000000000000000000000000000000000000000000000000000000000000f")]
+    fn matcher_fee_paid(&self, matcher_address: &Address, asset_address: &Address, amount: &BigUint);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000010")]
+    fn broker_fee_accrued(&self, broker_address: &Address, asset_address: &Address, amount: &BigUint);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000011")]
+    fn broker_fees_claimed(&self, broker_address: &Address, asset_address: &Address, amount: &BigUint);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000012")]
+    fn delegate_registered(&self, user_address: &Address, delegate_address: &Address, delegate: &Delegate);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000013")]
+    fn delegate_revoked(&self, user_address: &Address, delegate_address: &Address);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000014")]
+    fn withdrawal_queued(&self, user_address: &Address, asset_address: &Address, amount: &BigUint);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000015")]
+    fn queued_withdrawal_cancelled(&self, user_address: &Address, asset_address: &Address, amount: &BigUint);
+
+    #[event("0x0000000000000000000000000000000000000000000000000000000000000016")]
+    fn excess_rescued(&self, asset_address: &Address, to: &Address, amount: &BigUint);
 }
@@ -4,23 +4,76 @@
 #![allow(non_snake_case)]
 imports!();
 
-use common::{require};
+use common::{
+    checked_div, checked_sub, ensure_caller, ensure_not_zero, mul_div, require, require_eq, require_le,
+    require_ne, ErrorCode, ErrorMessage, Rounding,
+};
 
+mod asset;
+mod authorization;
+mod delegate;
 mod events;
+mod margin;
+mod oracle;
 mod order;
 mod order_status;
+mod pair_stats;
+mod self_trade;
+mod solvency;
+mod staking;
 mod token_proxy;
 mod trade;
+mod twap;
 
+use asset::{AssetInfo, AssetStatus};
+use authorization::{
+    CancellationAuthorization, InternalTransferAuthorization, SignedMessage,
+    WithdrawalAuthorization,
+};
+use delegate::{Delegate, DelegatePermissions};
 use events::*;
-use order::Order;
+use margin::{Liability, Position, PositionState, BASIS_POINTS};
+use oracle::{OracleMode, PriceFeed};
+use order::{LegacyOrder, Order, OrderSide};
 use order_status::OrderStatus;
-use token_proxy::TransferFrom;
+use pair_stats::{PairStats, PairStatsBucket};
+use self_trade::SelfTradePrevention;
+use solvency::Solvency;
+use staking::FeeTier;
+use token_proxy::{TokenRescue, TransferFrom};
 use trade::Trade;
+use twap::Observation;
 
 // ERD by convention is stored at the asset address of all zero in the asset_balance map
 static ERD_ASSET_ADDRESS: [u8; 32] = [0; 32];
 
+// Seconds a user must wait between requesting and executing a forced withdrawal, until the owner changes it
+static DEFAULT_FORCED_WITHDRAWAL_DELAY: u64 = 24 * 60 * 60;
+
+// Longest forced withdrawal delay the owner can set, so the escape hatch can't be closed with a huge delay
+static MAX_FORCED_WITHDRAWAL_DELAY: u64 = 7 * 24 * 60 * 60;
+
+// Version of the storage layout written by this code. Storage from before versioning was introduced reads as 0.
+static CURRENT_STORAGE_VERSION: u32 = 6;
+
+// Number of price observations kept per pair for `consult`, until the owner changes it
+static DEFAULT_OBSERVATION_CAPACITY: u64 = 48;
+
+// Seconds a price submitted by a price provider stays fresh enough to value margin positions, until the owner changes it
+static DEFAULT_PRICE_OVERDUE: u64 = 24 * 60 * 60;
+
+// Seconds a liability can stay open before the position is overdue, until the owner changes it
+static DEFAULT_POSITION_OVERDUE: u64 = 7 * 24 * 60 * 60;
+
+// Seconds between requesting an unstake and being able to withdraw it, until the owner changes it
+static DEFAULT_UNSTAKE_DELAY: u64 = 7 * 24 * 60 * 60;
+
+// Seconds a withdrawal above the queue threshold waits in the queue, until the owner changes it
+static DEFAULT_WITHDRAWAL_QUEUE_DELAY: u64 = 24 * 60 * 60;
+
+// Length of the periods daily withdrawal caps apply to, days start at midnight UTC
+static SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
 #[elrond_wasm_derive::contract(OrionExchangeImpl)]
 pub trait OrionExchange {
     /*------  Contract state  -------*/
@@ -43,17 +96,507 @@ pub trait OrionExchange {
     #[storage_get_mut("asset_balance")]
     fn get_asset_balance(&self, asset_address: &Address, user_address: &Address) -> mut_storage!(BigUint);
 
+    // Mapping: (user_address: Address, nonce: u64) => bool
+    #[view(isAuthorizationNonceUsed)]
+    #[storage_get("authorization_nonce_used")]
+    fn is_authorization_nonce_used(&self, user_address: &Address, nonce: u64) -> bool;
+    #[storage_set("authorization_nonce_used")]
+    fn set_authorization_nonce_used(&self, user_address: &Address, nonce: u64, used: bool);
+
+    // Mapping: (user_address, delegate_address) => timestamp after which the delegate is no longer active, 0 if not registered
+    #[storage_get("delegate_expiration")]
+    fn get_delegate_expiration(&self, user_address: &Address, delegate_address: &Address) -> u64;
+    #[storage_set("delegate_expiration")]
+    fn set_delegate_expiration(&self, user_address: &Address, delegate_address: &Address, expiration: u64);
+
+    #[storage_get("delegate_permissions")]
+    fn get_delegate_permissions(&self, user_address: &Address, delegate_address: &Address) -> DelegatePermissions;
+    #[storage_set("delegate_permissions")]
+    fn set_delegate_permissions(
+        &self,
+        user_address: &Address,
+        delegate_address: &Address,
+        permissions: &DelegatePermissions,
+    );
+
+    #[view(getStorageVersion)]
+    #[storage_get("storage_version")]
+    fn get_storage_version(&self) -> u32;
+    #[storage_set("storage_version")]
+    fn set_storage_version(&self, version: u32);
+
+    // Whether the owner can still move state that code from before versioning left under outdated keys.
+    // Only the migration to storage version 1 opens it, so once finished it can't be opened again.
+    #[view(isLegacyMigrationOpen)]
+    #[storage_get("legacy_migration_open")]
+    fn is_legacy_migration_open(&self) -> bool;
+    #[storage_set("legacy_migration_open")]
+    fn set_legacy_migration_open(&self, open: bool);
+
+    #[storage_get("owner")]
+    fn get_owner(&self) -> Address;
+    #[storage_set("owner")]
+    fn set_owner(&self, owner: &Address);
+
+    #[view(isPaused)]
+    #[storage_get("paused")]
+    fn is_paused(&self) -> bool;
+    #[storage_set("paused")]
+    fn set_paused(&self, paused: bool);
+
+    #[view(getSelfTradePrevention)]
+    #[storage_get("self_trade_prevention")]
+    fn get_self_trade_prevention(&self) -> SelfTradePrevention;
+    #[storage_set("self_trade_prevention")]
+    fn set_self_trade_prevention_storage(&self, mode: &SelfTradePrevention);
+
+    #[view(getForcedWithdrawalDelay)]
+    #[storage_get("forced_withdrawal_delay")]
+    fn get_forced_withdrawal_delay(&self) -> u64;
+    #[storage_set("forced_withdrawal_delay")]
+    fn set_forced_withdrawal_delay_storage(&self, delay: u64);
+
+    // Mapping: (asset_address: Address, user_address: Address) => BigUint
+    #[storage_get("forced_withdrawal_amount")]
+    fn get_forced_withdrawal_amount(&self, asset_address: &Address, user_address: &Address) -> BigUint;
+    #[storage_set("forced_withdrawal_amount")]
+    fn set_forced_withdrawal_amount(&self, asset_address: &Address, user_address: &Address, amount: &BigUint);
+
+    // Mapping: (asset_address: Address, user_address: Address) => timestamp the withdrawal can be executed
+    #[storage_get("forced_withdrawal_available_at")]
+    fn get_forced_withdrawal_available_at(&self, asset_address: &Address, user_address: &Address) -> u64;
+    #[storage_set("forced_withdrawal_available_at")]
+    fn set_forced_withdrawal_available_at(&self, asset_address: &Address, user_address: &Address, timestamp: u64);
+
+    // Mapping: (asset_address) => most that can be withdrawn from the exchange in a day, 0 if uncapped
+    #[view(getWithdrawalDailyCap)]
+    #[storage_get("withdrawal_daily_cap")]
+    fn get_withdrawal_daily_cap(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("withdrawal_daily_cap")]
+    fn set_withdrawal_daily_cap(&self, asset_address: &Address, cap: &BigUint);
+
+    // Mapping: (asset_address) => most that each user can withdraw in a day, 0 if uncapped
+    #[view(getUserWithdrawalDailyCap)]
+    #[storage_get("user_withdrawal_daily_cap")]
+    fn get_user_withdrawal_daily_cap(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("user_withdrawal_daily_cap")]
+    fn set_user_withdrawal_daily_cap(&self, asset_address: &Address, cap: &BigUint);
+
+    // Mapping: (asset_address) => day the withdrawn amount below is counted for
+    #[storage_get("withdrawal_day")]
+    fn get_withdrawal_day(&self, asset_address: &Address) -> u64;
+    #[storage_set("withdrawal_day")]
+    fn set_withdrawal_day(&self, asset_address: &Address, day: u64);
+
+    #[storage_get("withdrawn_in_day")]
+    fn get_withdrawn_in_day(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("withdrawn_in_day")]
+    fn set_withdrawn_in_day(&self, asset_address: &Address, amount: &BigUint);
+
+    #[storage_get("user_withdrawal_day")]
+    fn get_user_withdrawal_day(&self, asset_address: &Address, user_address: &Address) -> u64;
+    #[storage_set("user_withdrawal_day")]
+    fn set_user_withdrawal_day(&self, asset_address: &Address, user_address: &Address, day: u64);
+
+    #[storage_get("user_withdrawn_in_day")]
+    fn get_user_withdrawn_in_day(&self, asset_address: &Address, user_address: &Address) -> BigUint;
+    #[storage_set("user_withdrawn_in_day")]
+    fn set_user_withdrawn_in_day(&self, asset_address: &Address, user_address: &Address, amount: &BigUint);
+
+    // Mapping: (asset_address) => amount above which withdrawals are queued, 0 if they never are
+    #[view(getWithdrawalQueueThreshold)]
+    #[storage_get("withdrawal_queue_threshold")]
+    fn get_withdrawal_queue_threshold(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("withdrawal_queue_threshold")]
+    fn set_withdrawal_queue_threshold(&self, asset_address: &Address, threshold: &BigUint);
+
+    #[view(getWithdrawalQueueDelay)]
+    #[storage_get("withdrawal_queue_delay")]
+    fn get_withdrawal_queue_delay(&self) -> u64;
+    #[storage_set("withdrawal_queue_delay")]
+    fn set_withdrawal_queue_delay_storage(&self, delay: u64);
+
+    // Mapping: (asset_address, user_address) => amount taken from the balance and waiting in the queue
+    #[storage_get("queued_withdrawal_amount")]
+    fn get_queued_withdrawal_amount(&self, asset_address: &Address, user_address: &Address) -> BigUint;
+    #[storage_set("queued_withdrawal_amount")]
+    fn set_queued_withdrawal_amount(&self, asset_address: &Address, user_address: &Address, amount: &BigUint);
+
+    // Mapping: (asset_address, user_address) => timestamp from which the queued withdrawal can be executed
+    #[storage_get("queued_withdrawal_available_at")]
+    fn get_queued_withdrawal_available_at(&self, asset_address: &Address, user_address: &Address) -> u64;
+    #[storage_set("queued_withdrawal_available_at")]
+    fn set_queued_withdrawal_available_at(&self, asset_address: &Address, user_address: &Address, timestamp: u64);
+
+    // Account allowed to cancel queued withdrawals
+    #[view(getGuardian)]
+    #[storage_get("guardian")]
+    fn get_guardian(&self) -> Address;
+    #[storage_set("guardian")]
+    fn set_guardian_storage(&self, guardian_address: &Address);
+
+    // Mapping: (asset_address) => most the deposits minus the withdrawals of the asset can reach, 0 if uncapped
+    #[view(getDepositCap)]
+    #[storage_get("deposit_cap")]
+    fn get_deposit_cap(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("deposit_cap")]
+    fn set_deposit_cap_storage(&self, asset_address: &Address, cap: &BigUint);
+
+    // Mapping: (asset_address) => everything deposited minus everything withdrawn
+    #[view(getTotalDeposits)]
+    #[storage_get("total_deposits")]
+    fn get_total_deposits(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("total_deposits")]
+    fn set_total_deposits(&self, asset_address: &Address, amount: &BigUint);
+
+    // Mapping: (asset_address) => everything the users can get back of the asset, see Solvency
+    #[view(getTotalUserBalances)]
+    #[storage_get_mut("total_user_balances")]
+    fn get_total_user_balances(&self, asset_address: &Address) -> mut_storage!(BigUint);
+
+    // Mapping: (asset_address) => sum of the outstanding liabilities in the asset
+    #[view(getTotalLiabilities)]
+    #[storage_get_mut("total_liabilities")]
+    fn get_total_liabilities(&self, asset_address: &Address) -> mut_storage!(BigUint);
+
+    // Set by a fresh deploy, which keeps the totals above from the start. Storage upgraded from before they
+    // were kept reads false, see areUserBalanceTotalsIncomplete
+    #[storage_get("user_balance_totals_complete")]
+    fn get_user_balance_totals_complete(&self) -> bool;
+    #[storage_set("user_balance_totals_complete")]
+    fn set_user_balance_totals_complete(&self, complete: bool);
+
+    // Mapping: (asset_address) => whether the owner has seeded the totals of the asset and completed them
+    #[storage_get("user_balance_totals_completed")]
+    fn is_user_balance_totals_completed(&self, asset_address: &Address) -> bool;
+    #[storage_set("user_balance_totals_completed")]
+    fn set_user_balance_totals_completed(&self, asset_address: &Address, completed: bool);
+
+    // Mapping: (asset_address) => whether the owner has started seeding the totals of the asset
+    #[storage_get("user_balance_totals_seeding")]
+    fn is_user_balance_totals_seeding(&self, asset_address: &Address) -> bool;
+    #[storage_set("user_balance_totals_seeding")]
+    fn set_user_balance_totals_seeding(&self, asset_address: &Address, seeding: bool);
+
+    // Number of assets whose totals are being seeded, the exchange can't be unpaused while it is not zero
+    #[storage_get("user_balance_totals_seeding_count")]
+    fn get_user_balance_totals_seeding_count(&self) -> u32;
+    #[storage_set("user_balance_totals_seeding_count")]
+    fn set_user_balance_totals_seeding_count(&self, count: u32);
+
+    // Mapping: (asset_address, user_address) => whether the user has been counted in the seeded totals
+    #[storage_get("user_balance_totals_seeded")]
+    fn is_user_balance_totals_seeded(&self, asset_address: &Address, user_address: &Address) -> bool;
+    #[storage_set("user_balance_totals_seeded")]
+    fn set_user_balance_totals_seeded(&self, asset_address: &Address, user_address: &Address, seeded: bool);
+
+    // Mapping: (asset_address) => token balance of the exchange reported by the token contract, plus the
+    // deposits and minus the withdrawals and rescues since
+    #[storage_get("token_holdings")]
+    fn get_token_holdings(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("token_holdings")]
+    fn set_token_holdings(&self, asset_address: &Address, holdings: &BigUint);
+
+    #[storage_get("token_holdings_timestamp")]
+    fn get_token_holdings_timestamp(&self, asset_address: &Address) -> u64;
+    #[storage_set("token_holdings_timestamp")]
+    fn set_token_holdings_timestamp(&self, asset_address: &Address, timestamp: u64);
+
+    // Assets listed by the owner, in the order they were listed
+    #[view(getListedAssets)]
+    #[storage_get("listed_assets")]
+    fn get_listed_assets(&self) -> Vec<Address>;
+    #[storage_set("listed_assets")]
+    fn set_listed_assets(&self, asset_addresses: &Vec<Address>);
+
+    #[storage_get("asset_ticker")]
+    fn get_asset_ticker(&self, asset_address: &Address) -> Vec<u8>;
+    #[storage_set("asset_ticker")]
+    fn set_asset_ticker(&self, asset_address: &Address, ticker: &Vec<u8>);
+
+    #[storage_get("asset_decimals")]
+    fn get_asset_decimals(&self, asset_address: &Address) -> u8;
+    #[storage_set("asset_decimals")]
+    fn set_asset_decimals(&self, asset_address: &Address, decimals: u8);
+
+    #[storage_get("asset_status")]
+    fn get_asset_status(&self, asset_address: &Address) -> AssetStatus;
+    #[storage_set("asset_status")]
+    fn set_asset_status_storage(&self, asset_address: &Address, status: &AssetStatus);
+
+    // Mapping: (base_asset: Address, quote_asset: Address) => price of the last trade on the pair
+    #[view(getLastPrice)]
+    #[storage_get("last_price")]
+    fn get_last_price(&self, base_asset: &Address, quote_asset: &Address) -> BigUint;
+    #[storage_set("last_price")]
+    fn set_last_price(&self, base_asset: &Address, quote_asset: &Address, price: &BigUint);
+
+    // Mapping: (base_asset: Address, quote_asset: Address) => hourly trade buckets of the last 24h
+    #[storage_get("pair_stats_buckets")]
+    fn get_pair_stats_buckets(&self, base_asset: &Address, quote_asset: &Address) -> Vec<PairStatsBucket<BigUint>>;
+    #[storage_set("pair_stats_buckets")]
+    fn set_pair_stats_buckets(&self, base_asset: &Address, quote_asset: &Address, buckets: &Vec<PairStatsBucket<BigUint>>);
+
+    // Mapping: (base_asset: Address, quote_asset: Address) => cumulative price as of the last trade
+    #[storage_get("price_cumulative_last")]
+    fn get_price_cumulative_last(&self, base_asset: &Address, quote_asset: &Address) -> BigUint;
+    #[storage_set("price_cumulative_last")]
+    fn set_price_cumulative_last(&self, base_asset: &Address, quote_asset: &Address, price_cumulative: &BigUint);
+
+    // Mapping: (base_asset: Address, quote_asset: Address) => timestamp of the last trade
+    #[storage_get("last_trade_timestamp")]
+    fn get_last_trade_timestamp(&self, base_asset: &Address, quote_asset: &Address) -> u64;
+    #[storage_set("last_trade_timestamp")]
+    fn set_last_trade_timestamp(&self, base_asset: &Address, quote_asset: &Address, timestamp: u64);
+
+    // Mapping: (base_asset: Address, quote_asset: Address) => Vec<Observation>, oldest first
+    #[view(getObservations)]
+    #[storage_get("price_observations")]
+    fn get_price_observations(&self, base_asset: &Address, quote_asset: &Address) -> Vec<Observation<BigUint>>;
+    #[storage_set("price_observations")]
+    fn set_price_observations(&self, base_asset: &Address, quote_asset: &Address, observations: &Vec<Observation<BigUint>>);
+
+    // Mapping: (user_address: Address) => Vec<Liability>
+    #[view(getLiabilities)]
+    #[storage_get("liabilities")]
+    fn get_liabilities(&self, user_address: &Address) -> Vec<Liability<BigUint>>;
+    #[storage_set("liabilities")]
+    fn set_liabilities(&self, user_address: &Address, liabilities: &Vec<Liability<BigUint>>);
+
+    #[view(getCollateralAssets)]
+    #[storage_get("collateral_assets")]
+    fn get_collateral_assets(&self) -> Vec<Address>;
+    #[storage_set("collateral_assets")]
+    fn set_collateral_assets(&self, asset_addresses: &Vec<Address>);
+
+    // Mapping: (asset_address: Address) => share of the collateral value counted in the position, in basis points
+    #[view(getAssetRisk)]
+    #[storage_get("asset_risk")]
+    fn get_asset_risk(&self, asset_address: &Address) -> u64;
+    #[storage_set("asset_risk")]
+    fn set_asset_risk(&self, asset_address: &Address, risk: u64);
+
+    #[view(getPriceOverdue)]
+    #[storage_get("price_overdue")]
+    fn get_price_overdue(&self) -> u64;
+    #[storage_set("price_overdue")]
+    fn set_price_overdue(&self, seconds: u64);
+
+    #[view(getPositionOverdue)]
+    #[storage_get("position_overdue")]
+    fn get_position_overdue(&self) -> u64;
+    #[storage_set("position_overdue")]
+    fn set_position_overdue(&self, seconds: u64);
+
+    #[view(getPriceProviders)]
+    #[storage_get("price_providers")]
+    fn get_price_providers(&self) -> Vec<Address>;
+    #[storage_set("price_providers")]
+    fn set_price_providers_storage(&self, provider_addresses: &Vec<Address>);
+
+    #[view(getOracleMode)]
+    #[storage_get("oracle_mode")]
+    fn get_oracle_mode(&self) -> OracleMode;
+    #[storage_set("oracle_mode")]
+    fn set_oracle_mode_storage(&self, mode: &OracleMode);
+
+    // Provider whose prices are used as they are in the TrustedFeed mode, must also be a price provider
+    #[view(getTrustedPriceProvider)]
+    #[storage_get("trusted_price_provider")]
+    fn get_trusted_price_provider(&self) -> Address;
+    #[storage_set("trusted_price_provider")]
+    fn set_trusted_price_provider_storage(&self, provider_address: &Address);
+
+    // Mapping: (asset_address: Address, provider_address: Address) => latest price submitted by the provider
+    #[storage_get("provider_price")]
+    fn get_provider_price(&self, asset_address: &Address, provider_address: &Address) -> BigUint;
+    #[storage_set("provider_price")]
+    fn set_provider_price(&self, asset_address: &Address, provider_address: &Address, price: &BigUint);
+
+    // Mapping: (asset_address: Address, provider_address: Address) => timestamp of the latest price submitted by the provider
+    #[storage_get("provider_price_timestamp")]
+    fn get_provider_price_timestamp(&self, asset_address: &Address, provider_address: &Address) -> u64;
+    #[storage_set("provider_price_timestamp")]
+    fn set_provider_price_timestamp(&self, asset_address: &Address, provider_address: &Address, timestamp: u64);
+
+    // Extra value, in basis points of the repaid liability, a liquidator receives in collateral
+    #[view(getLiquidationPremium)]
+    #[storage_get("liquidation_premium")]
+    fn get_liquidation_premium(&self) -> u64;
+    #[storage_set("liquidation_premium")]
+    fn set_liquidation_premium_storage(&self, premium: u64);
+
+    // Staking is disabled while this is the zero address
+    #[view(getStakingAsset)]
+    #[storage_get("staking_asset")]
+    fn get_staking_asset(&self) -> Address;
+    #[storage_set("staking_asset")]
+    fn set_staking_asset(&self, asset_address: &Address);
+
+    #[view(getUnstakeDelay)]
+    #[storage_get("unstake_delay")]
+    fn get_unstake_delay(&self) -> u64;
+    #[storage_set("unstake_delay")]
+    fn set_unstake_delay(&self, delay: u64);
+
+    // Share of the staked value counted in the margin position, in basis points
+    #[view(getStakeRisk)]
+    #[storage_get("stake_risk")]
+    fn get_stake_risk(&self) -> u64;
+    #[storage_set("stake_risk")]
+    fn set_stake_risk(&self, risk: u64);
+
+    // Mapping: (user_address: Address) => amount of the staking asset locked in the stake
+    #[storage_get("staked_amount")]
+    fn get_staked_amount(&self, user_address: &Address) -> BigUint;
+    #[storage_set("staked_amount")]
+    fn set_staked_amount(&self, user_address: &Address, amount: &BigUint);
+
+    // Amount of the staking asset staked or unstaking over all users, the staking asset cannot change while
+    // it is not zero
+    #[view(getTotalStaked)]
+    #[storage_get("total_staked")]
+    fn get_total_staked(&self) -> BigUint;
+    #[storage_set("total_staked")]
+    fn set_total_staked(&self, amount: &BigUint);
+
+    // Mapping: (user_address: Address) => amount requested to be unstaked and not withdrawn yet
+    #[storage_get("unstaking_amount")]
+    fn get_unstaking_amount(&self, user_address: &Address) -> BigUint;
+    #[storage_set("unstaking_amount")]
+    fn set_unstaking_amount(&self, user_address: &Address, amount: &BigUint);
+
+    // Mapping: (user_address: Address) => timestamp the unstaking amount can be withdrawn
+    #[storage_get("unstake_available_at")]
+    fn get_unstake_available_at(&self, user_address: &Address) -> u64;
+    #[storage_set("unstake_available_at")]
+    fn set_unstake_available_at(&self, user_address: &Address, timestamp: u64);
+
+    #[view(getFeeTiers)]
+    #[storage_get("fee_tiers")]
+    fn get_fee_tiers(&self) -> Vec<FeeTier<BigUint>>;
+    #[storage_set("fee_tiers")]
+    fn set_fee_tiers_storage(&self, tiers: &Vec<FeeTier<BigUint>>);
+
+    // Assets orders may pay their matcher fee in. Any asset is accepted while the list is empty.
+    #[view(getFeeAssets)]
+    #[storage_get("fee_assets")]
+    fn get_fee_assets(&self) -> Vec<Address>;
+    #[storage_set("fee_assets")]
+    fn set_fee_assets(&self, asset_addresses: &Vec<Address>);
+
+    // Mapping: (asset_address: Address) => ERD value of one unit of the fee asset, zero to use the oracle price
+    #[view(getFeeAssetRate)]
+    #[storage_get("fee_asset_rate")]
+    fn get_fee_asset_rate(&self, asset_address: &Address) -> BigUint;
+    #[storage_set("fee_asset_rate")]
+    fn set_fee_asset_rate(&self, asset_address: &Address, rate: &BigUint);
+
+    // Mapping: (asset_address: Address) => discount off the required fee when paying in the asset, in basis points
+    #[view(getFeeAssetDiscount)]
+    #[storage_get("fee_asset_discount")]
+    fn get_fee_asset_discount(&self, asset_address: &Address) -> u64;
+    #[storage_set("fee_asset_discount")]
+    fn set_fee_asset_discount(&self, asset_address: &Address, discount: u64);
+
+    // Minimum matcher fee of an order, in basis points of the order's value
+    #[view(getMatcherFeeRate)]
+    #[storage_get("matcher_fee_rate")]
+    fn get_matcher_fee_rate(&self) -> u64;
+    #[storage_set("matcher_fee_rate")]
+    fn set_matcher_fee_rate_storage(&self, rate: u64);
+
+    // Mapping: (broker_address: Address) => share of the matcher fee of the broker's orders paid to the broker, in basis points
+    #[view(getBrokerShare)]
+    #[storage_get("broker_share")]
+    fn get_broker_share(&self, broker_address: &Address) -> u64;
+    #[storage_set("broker_share")]
+    fn set_broker_share_storage(&self, broker_address: &Address, share: u64);
+
+    // Mapping: (asset_address: Address, broker_address: Address) => fees the broker can claim
+    #[view(getBrokerFees)]
+    #[storage_get("broker_fees")]
+    fn get_broker_fees(&self, asset_address: &Address, broker_address: &Address) -> BigUint;
+    #[storage_set("broker_fees")]
+    fn set_broker_fees(&self, asset_address: &Address, broker_address: &Address, amount: &BigUint);
+
+    #[view(getObservationCapacity)]
+    #[storage_get("observation_capacity")]
+    fn get_observation_capacity(&self) -> u64;
+    #[storage_set("observation_capacity")]
+    fn set_observation_capacity_storage(&self, capacity: u64);
+
 
     /*----------  views  ----------*/
 
     #[view(getBalances)]
-    fn get_balances(&self, asset_addresses: &Vec<Address>, user_address: &Address) -> Vec<BigUint> {
+    fn get_balances(
+        &self,
+        asset_addresses: &Vec<Address>,
+        user_address: &Address,
+        sub_account: u64,
+    ) -> Vec<BigUint> {
+        let account_address = self.get_sub_account_address(user_address, sub_account);
         asset_addresses
             .iter()
-            .map(|asset_address| self.get_asset_balance(asset_address, user_address).clone())
+            .map(|asset_address| self.get_asset_balance(asset_address, &account_address).clone())
+            .collect()
+    }
+
+    // Sub-account 0 is the user's own address. Numbered sub-accounts hold their balances, liabilities and
+    // stake under an address derived from the user's address and the number, which nobody has the key of.
+    #[view(getSubAccountAddress)]
+    fn get_sub_account_address(&self, user_address: &Address, sub_account: u64) -> Address {
+        if sub_account == 0 {
+            return user_address.clone();
+        }
+        let mut payload = Vec::new();
+        payload.extend_from_slice(user_address.as_bytes());
+        payload.extend_from_slice(&sub_account.to_be_bytes());
+        self.keccak256(payload.as_slice()).into()
+    }
+
+    #[view(getAssets)]
+    fn get_assets(&self) -> Vec<AssetInfo> {
+        self.get_listed_assets()
+            .iter()
+            .map(|asset_address| AssetInfo {
+                asset_address: asset_address.clone(),
+                ticker: self.get_asset_ticker(asset_address),
+                decimals: self.get_asset_decimals(asset_address),
+                status: self.get_asset_status(asset_address),
+            })
             .collect()
     }
 
+    // Whether the totals of the asset still have to be seeded, for storage from before they were kept
+    #[view(areUserBalanceTotalsIncomplete)]
+    fn are_user_balance_totals_incomplete(&self, asset_address: &Address) -> bool {
+        !self.get_user_balance_totals_complete() && !self.is_user_balance_totals_completed(asset_address)
+    }
+
+    #[view(getSolvency)]
+    fn get_solvency(&self, asset_address: &Address) -> SCResult<Solvency<BigUint>> {
+        require!(
+            !self.are_user_balance_totals_incomplete(asset_address),
+            ErrorCode::UserBalanceTotalsIncomplete
+        );
+        let (holdings, holdings_timestamp) = if asset_address == &(ERD_ASSET_ADDRESS.into()) {
+            (self.get_balance(&self.get_sc_address()), self.get_block_timestamp())
+        } else {
+            (
+                self.get_token_holdings(asset_address),
+                self.get_token_holdings_timestamp(asset_address),
+            )
+        };
+        Ok(Solvency {
+            holdings,
+            holdings_timestamp,
+            total_user_balances: self.get_total_user_balances(asset_address).clone(),
+            total_liabilities: self.get_total_liabilities(asset_address).clone(),
+        })
+    }
+
     #[view(getOrderTrades)]
     fn get_order_trades_public(&self, order: &Order<BigUint>) -> SCResult<Vec<Trade<BigUint>>> {
         let order_hash = sc_try!(self.hash_order(order));
@@ -78,14 +621,142 @@ pub trait OrionExchange {
     fn is_order_cancelled(&self, order_hash: &H256) -> bool {
         let order_status = self.get_order_status(order_hash);
         match order_status {
-            OrderStatus::Cancelled | OrderStatus::PartiallyCancelled => false,
-            _ => true,
+            OrderStatus::Cancelled | OrderStatus::PartiallyCancelled => true,
+            _ => false,
+        }
+    }
+
+    #[view(getPairStats)]
+    fn get_pair_stats(&self, base_asset: &Address, quote_asset: &Address) -> PairStats<BigUint> {
+        let buckets = self.get_pair_stats_buckets(base_asset, quote_asset);
+        PairStats::from_buckets(&buckets, self.get_block_timestamp())
+    }
+
+    // Cumulative price of the pair up to the current block, for contracts that keep their own observations
+    #[view(getPriceCumulative)]
+    fn get_price_cumulative(&self, base_asset: &Address, quote_asset: &Address) -> BigUint {
+        self.current_price_cumulative(base_asset, quote_asset, self.get_block_timestamp())
+    }
+
+    // Time-weighted average price of the pair from the newest observation at least `window` seconds old up to now
+    #[view(consult)]
+    fn consult(&self, base_asset: &Address, quote_asset: &Address, window: u64) -> SCResult<BigUint> {
+        ensure_not_zero!(window);
+        let now = self.get_block_timestamp();
+        let observations = self.get_price_observations(base_asset, quote_asset);
+        let observation = match twap::observation_before(&observations, now, window) {
+            Some(observation) => observation,
+            None => return sc_error!(ErrorCode::InsufficientObservations.error_message()),
+        };
+
+        let price_cumulative = self.current_price_cumulative(base_asset, quote_asset, now);
+        let price_delta = sc_try!(checked_sub!(price_cumulative, observation.price_cumulative));
+        checked_div!(price_delta, BigUint::from(now - observation.timestamp))
+    }
+
+    // returns (price, age) of the oracle price of the asset in ERD, age being the seconds since its timestamp
+    #[view(getPrice)]
+    fn get_price(&self, asset_address: &Address) -> SCResult<(BigUint, u64)> {
+        match self.get_oracle_price(asset_address) {
+            Some((price, timestamp)) => Ok((price, self.get_block_timestamp() - timestamp)),
+            None => sc_error!(ErrorCode::NoFreshPrice.error_message()),
+        }
+    }
+
+    // returns (price, timestamp) of the latest price the provider submitted for the asset, fresh or not
+    #[view(getProviderPrice)]
+    fn get_provider_price_public(&self, asset_address: &Address, provider_address: &Address) -> (BigUint, u64) {
+        (
+            self.get_provider_price(asset_address, provider_address),
+            self.get_provider_price_timestamp(asset_address, provider_address),
+        )
+    }
+
+    // returns (staked, unstaking, available_at), available_at being when the unstaking amount can be withdrawn
+    #[view(getStake)]
+    fn get_stake(&self, user_address: &Address) -> (BigUint, BigUint, u64) {
+        (
+            self.get_staked_amount(user_address),
+            self.get_unstaking_amount(user_address),
+            self.get_unstake_available_at(user_address),
+        )
+    }
+
+    // Discount in basis points off the matcher fee of the user's orders
+    #[view(getFeeDiscount)]
+    fn get_fee_discount(&self, user_address: &Address) -> u64 {
+        staking::fee_discount(&self.get_fee_tiers(), &self.get_staked_amount(user_address))
+    }
+
+    // Smallest matcher_fee the order may be signed with, in its matcher_fee_asset
+    #[view(getRequiredMatcherFee)]
+    fn get_required_matcher_fee(&self, order: &Order<BigUint>) -> SCResult<BigUint> {
+        let quote_price = match self.get_asset_price(&order.quote_asset) {
+            Some(price) => price,
+            None => return sc_error!(ErrorCode::NoFreshPrice.error_message()),
+        };
+        let fee_asset_rate = self.get_fee_asset_rate(&order.matcher_fee_asset);
+        let fee_asset_price = if fee_asset_rate > 0 {
+            fee_asset_rate
+        } else {
+            match self.get_asset_price(&order.matcher_fee_asset) {
+                Some(price) => price,
+                None => return sc_error!(ErrorCode::NoFreshPrice.error_message()),
+            }
+        };
+
+        let order_value = order.amount.clone() * order.price.clone() * quote_price;
+        let discount = self.get_fee_asset_discount(&order.matcher_fee_asset);
+        let fee_factor = BigUint::from(self.get_matcher_fee_rate() * (BASIS_POINTS - discount));
+        let divisor = BigUint::from(BASIS_POINTS * BASIS_POINTS) * fee_asset_price;
+        mul_div!(order_value, fee_factor, divisor, Rounding::Down)
+    }
+
+    #[view(calcPosition)]
+    fn calc_position(&self, user_address: &Address) -> Position<BigUint> {
+        self.calculate_position(user_address, None)
+    }
+
+    // returns (amount, available_at) of the pending forced withdrawal, amount is zero if there is none
+    #[view(getForcedWithdrawal)]
+    fn get_forced_withdrawal(&self, asset_address: &Address, user_address: &Address) -> (BigUint, u64) {
+        (
+            self.get_forced_withdrawal_amount(asset_address, user_address),
+            self.get_forced_withdrawal_available_at(asset_address, user_address),
+        )
+    }
+
+    // returns (amount, available_at) of the queued withdrawal, amount is zero if there is none
+    #[view(getQueuedWithdrawal)]
+    fn get_queued_withdrawal(&self, asset_address: &Address, user_address: &Address) -> (BigUint, u64) {
+        (
+            self.get_queued_withdrawal_amount(asset_address, user_address),
+            self.get_queued_withdrawal_available_at(asset_address, user_address),
+        )
+    }
+
+    #[view(getDelegate)]
+    fn get_delegate(&self, user_address: &Address, delegate_address: &Address) -> Delegate {
+        Delegate {
+            expiration: self.get_delegate_expiration(user_address, delegate_address),
+            permissions: self.get_delegate_permissions(user_address, delegate_address),
         }
     }
 
+    // Whether the key can currently sign orders for the user
+    #[view(isActiveDelegate)]
+    fn is_active_delegate(&self, user_address: &Address, delegate_address: &Address) -> bool {
+        let expiration = self.get_delegate_expiration(user_address, delegate_address);
+        expiration != 0 && expiration >= self.get_block_timestamp()
+    }
+
     #[view(validateOrder)]
     fn validate_order(&self, order: &Order<BigUint>) -> bool {
-        match order.validate() {
+        let order_hash = match self.hash_order(order) {
+            Ok(order_hash) => order_hash,
+            _ => return false,
+        };
+        match self.check_order_signature(order, &order_hash) {
             Ok(_) => true,
             _ => false,
         }
@@ -95,6 +766,11 @@ pub trait OrionExchange {
 
     #[endpoint(depositAsset)]
     fn deposit_asset(&self, asset_address: &Address, amount: BigUint) -> SCResult<()> {
+        require!(
+            self.get_effective_asset_status(asset_address).allows_deposits(),
+            ErrorCode::DepositsDisabled
+        );
+        sc_try!(self.check_deposit_cap(asset_address, &amount));
         let token_contract = contract_proxy!(self, asset_address, TransferFrom);
         token_contract.transferFrom(
             asset_address,
@@ -110,41 +786,82 @@ pub trait OrionExchange {
     #[payable]
     #[endpoint(depositERD)]
     fn deposit_erd(&self, #[payment] payment: &BigUint) -> SCResult<()> {
+        require!(
+            self.get_effective_asset_status(&ERD_ASSET_ADDRESS.into()).allows_deposits(),
+            ErrorCode::DepositsDisabled
+        );
+        sc_try!(self.check_deposit_cap(&ERD_ASSET_ADDRESS.into(), payment));
         self.asset_deposit(&ERD_ASSET_ADDRESS.into(), &self.get_caller(), payment)
     }
 
     #[endpoint]
     fn withdraw(&self, asset_address: &Address, amount: &BigUint) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        self.request_withdraw(&self.get_caller(), asset_address, amount)
+    }
+
+    #[endpoint(internalTransfer)]
+    fn internal_transfer(
+        &self,
+        asset_address: &Address,
+        to: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        self.asset_transfer(asset_address, &self.get_caller(), to, amount)
+    }
+
+    // Moves funds between two of the caller's sub-accounts, free of fees like any internal transfer
+    #[endpoint(subAccountTransfer)]
+    fn sub_account_transfer(
+        &self,
+        asset_address: &Address,
+        from_sub_account: u64,
+        to_sub_account: u64,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
         let caller = self.get_caller();
-        if asset_address == &(ERD_ASSET_ADDRESS.into()) {
-            // TODO: can this handle transaction failures?
-            self.send_tx(&caller, amount, "");
-            self.asset_withdrawl(&ERD_ASSET_ADDRESS.into(), &caller, amount)
-        } else {
-            let token_contract = contract_proxy!(self, asset_address, TransferFrom);
-            token_contract.transfer(
-                asset_address,
-                &self.get_caller(),
-                amount.clone(),
-                &self.get_caller(),
-                amount.clone(),
-            );
-            Ok(())
+        let from = self.get_sub_account_address(&caller, from_sub_account);
+        let to = self.get_sub_account_address(&caller, to_sub_account);
+        self.asset_transfer(asset_address, &from, &to, amount)
+    }
+
+    #[endpoint(batchInternalTransfer)]
+    fn batch_internal_transfer(
+        &self,
+        asset_address: &Address,
+        recipients: &Vec<Address>,
+        amounts: &Vec<BigUint>,
+    ) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        require!(
+            recipients.len() == amounts.len(),
+            ErrorCode::LengthMismatch
+        );
+        let caller = self.get_caller();
+        for (to, amount) in recipients.iter().zip(amounts.iter()) {
+            sc_try!(self.asset_transfer(asset_address, &caller, to, amount));
         }
+        Ok(())
     }
 
     #[endpoint(fillOrders)]
     fn fill_orders(
         &self,
-        buy_order: Order<BigUint>,
-        sell_order: Order<BigUint>,
+        first_order: Order<BigUint>,
+        second_order: Order<BigUint>,
         filled_price: BigUint,
         filled_amount: BigUint,
     ) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        let (buy_order, sell_order) = sc_try!(Order::into_buy_and_sell(first_order, second_order));
         let amount_quote = filled_amount.clone() * filled_price.clone();
 
         let buy_order_hash = sc_try!(self.hash_order(&buy_order));
         let sell_order_hash = sc_try!(self.hash_order(&sell_order));
+        sc_try!(self.check_order_signature(&buy_order, &buy_order_hash));
+        sc_try!(self.check_order_signature(&sell_order, &sell_order_hash));
 
         sc_try!(Order::check_orders_info(
             &buy_order,
@@ -152,39 +869,70 @@ pub trait OrionExchange {
             &self.get_caller(),
             filled_amount.clone(),
             filled_price.clone(),
-            self.get_block_timestamp()
+            self.get_block_timestamp(),
+            &|asset_address: &Address| self.get_effective_asset_status(asset_address).allows_trading()
         ));
 
-        require!(!self.is_order_cancelled(&buy_order_hash), "E4");
-        require!(!self.is_order_cancelled(&sell_order_hash), "E4");
+        sc_try!(self.check_matcher_fee(&buy_order));
+        sc_try!(self.check_matcher_fee(&sell_order));
+
+        require!(!self.is_order_cancelled(&buy_order_hash), ErrorCode::OrderCancelled);
+        require!(!self.is_order_cancelled(&sell_order_hash), ErrorCode::OrderCancelled);
+
+        if buy_order.sender_address == sell_order.sender_address {
+            return self.prevent_self_trade(&buy_order, &sell_order);
+        }
+
+        sc_try!(self.check_triggered(&buy_order, &buy_order_hash));
+        sc_try!(self.check_triggered(&sell_order, &sell_order_hash));
+
+        // fills are settled on the sub-accounts the orders were signed for
+        let buyer = self.get_sub_account_address(&buy_order.sender_address, buy_order.sub_account);
+        let seller = self.get_sub_account_address(&sell_order.sender_address, sell_order.sub_account);
+
+        let buy_matcher_fee = sc_try!(self.calculate_fill_fee(&buy_order, &buyer, &filled_amount));
+        let sell_matcher_fee = sc_try!(self.calculate_fill_fee(&sell_order, &seller, &filled_amount));
 
         // state updates
         sc_try!(self.update_order_balance(
+            &buyer,
             buy_order.clone(),
             filled_amount.clone(),
             amount_quote.clone(),
-            true,
+            buy_matcher_fee.clone(),
         ));
         sc_try!(self.update_order_balance(
+            &seller,
             sell_order.clone(),
             filled_amount.clone(),
             amount_quote.clone(),
-            false,
+            sell_matcher_fee.clone(),
         ));
 
+        // fills may only borrow as far as the collateral allows, and an indebted user may not trade away
+        // the collateral that backs their liabilities
+        sc_try!(self.check_position(&buyer, None));
+        sc_try!(self.check_position(&seller, None));
+
         sc_try!(self.update_trade(
             &buy_order_hash,
             buy_order.clone(),
             filled_amount.clone(),
             filled_price.clone(),
+            buy_matcher_fee,
         ));
         sc_try!(self.update_trade(
             &sell_order_hash,
             sell_order.clone(),
             filled_amount.clone(),
             filled_price.clone(),
+            sell_matcher_fee,
         ));
 
+        self.update_price_oracle(&buy_order.base_asset, &buy_order.quote_asset);
+        self.set_last_price(&buy_order.base_asset, &buy_order.quote_asset, &filled_price);
+        self.record_pair_trade(&buy_order.base_asset, &buy_order.quote_asset, &filled_price, &filled_amount, &amount_quote);
+
         self.events().new_trade(
             &buy_order.sender_address,
             &sell_order.sender_address,
@@ -198,139 +946,1720 @@ pub trait OrionExchange {
         Ok(())
     }
 
-    #[endpoint(cancelOrder)]
-    fn cancel_order(&self, order: &Order<BigUint>) -> SCResult<()> {
-        let caller = self.get_caller();
-        sc_try!(order.validate());
-        require!(order.sender_address == caller, "Only the owner of an order can cancel");
-
-        let order_hash = sc_try!(self.hash_order(order));
+    // Fails if the exchange holds less ERD than it owes. For tokens the holdings are queried from the token
+    // contract and checked in the callback, which records them for getSolvency if the exchange is solvent.
+    #[endpoint(assertSolvent)]
+    fn assert_solvent(&self, asset_address: &Address) -> SCResult<()> {
+        if asset_address == &(ERD_ASSET_ADDRESS.into()) {
+            let solvency = sc_try!(self.get_solvency(asset_address));
+            require!(solvency.is_solvent(), ErrorCode::Insolvent);
+        } else {
+            require!(
+                !self.are_user_balance_totals_incomplete(asset_address),
+                ErrorCode::UserBalanceTotalsIncomplete
+            );
+            let token_contract = contract_proxy!(self, asset_address, TransferFrom);
+            token_contract.balanceOf(asset_address, &self.get_sc_address());
+        }
+        Ok(())
+    }
 
+    // Can be relayed by any account, the price is only accepted with a valid signature of the provider
+    #[endpoint(submitPrice)]
+    fn submit_price(&self, feed: PriceFeed<BigUint>) -> SCResult<()> {
         require!(
-            !self.is_order_cancelled(&order_hash),
-            "Order already cancelled"
+            self.get_price_providers().contains(&feed.provider_address),
+            ErrorCode::UnknownPriceProvider
         );
+        ensure_not_zero!(feed.price);
 
-        let (total_filled, _) = sc_try!(self.get_filled_amounts(order));
+        let now = self.get_block_timestamp();
+        let last_timestamp = self.get_provider_price_timestamp(&feed.asset_address, &feed.provider_address);
+        require!(
+            feed.timestamp <= now
+                && feed.timestamp + self.get_price_overdue() >= now
+                && feed.timestamp > last_timestamp,
+            ErrorCode::InvalidPriceTimestamp
+        );
 
-        if total_filled > 0 {
-            self.set_order_status(&order_hash, &OrderStatus::PartiallyCancelled)
+        if let Result::Ok(payload) = feed.signed_payload(&self.get_sc_address()) {
+            let payload_hash = self.keccak256(payload.as_slice());
+            require!(
+                self.verify_ed25519(
+                    feed.provider_address.as_bytes(),
+                    &payload_hash[..],
+                    feed.signature.as_slice()
+                ),
+                ErrorCode::InvalidSignature
+            );
         } else {
-            self.set_order_status(&order_hash, &OrderStatus::Cancelled)
+            return sc_error!(ErrorCode::EncodingFailed.error_message());
         }
 
-        self.events().order_update(
-            &order_hash,
-            &caller,
-            &self.get_order_status(&order_hash),
-        );
+        self.set_provider_price(&feed.asset_address, &feed.provider_address, &feed.price);
+        self.set_provider_price_timestamp(&feed.asset_address, &feed.provider_address, feed.timestamp);
+        self.events()
+            .price_submitted(&feed.provider_address, &feed.asset_address, &feed.price);
         Ok(())
     }
 
-    /*----------  callbacks (used internally)  ----------*/
+    // Repays `amount` of the broker's liability in `redeem_asset` from the caller's balance. The caller is
+    // reimbursed with the broker's collateral, in the order of the collateral assets, worth the repaid
+    // amount plus the liquidation premium.
+    #[endpoint(partiallyLiquidate)]
+    fn partially_liquidate(&self, broker: &Address, redeem_asset: &Address, amount: &BigUint) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        ensure_not_zero!(*amount);
 
-    #[callback]
-    fn asset_deposit_callback(
-        &self,
-        call_result: AsyncCallResult<()>,
-        #[callback_arg] cb_asset_address: &Address,
-        #[callback_arg] cb_account_address: &Address,
-        #[callback_arg] cb_amount: BigUint,
-    ) -> elrond_wasm::SCResult<()> {
-        if let AsyncCallResult::Ok(()) = call_result {
-            self.asset_deposit(cb_asset_address, cb_account_address, &cb_amount)
-        } else {
-            sc_error!("Error completing asset deposit")
+        let initial_position = self.calculate_position(broker, None);
+        require!(
+            initial_position.state == PositionState::Negative
+                || initial_position.state == PositionState::Overdue,
+            ErrorCode::NotLiquidatable
+        );
+
+        let outstanding_amount = self
+            .get_liabilities(broker)
+            .iter()
+            .find(|liability| &liability.asset == redeem_asset)
+            .map(|liability| liability.outstanding_amount.clone())
+            .unwrap_or_else(BigUint::zero);
+        require_le!(*amount, outstanding_amount, ErrorCode::LiquidationTooLarge);
+
+        let liquidator = self.get_caller();
+        {
+            let mut liquidator_balance = self.get_asset_balance(redeem_asset, &liquidator);
+            *liquidator_balance =
+                sc_try!(checked_sub!(*liquidator_balance, *amount, ErrorCode::InsufficientBalance));
         }
+        self.credit_balance(redeem_asset, broker, amount);
+        self.events()
+            .position_liquidated(broker, &liquidator, redeem_asset, amount);
+
+        let price = match self.get_asset_price(redeem_asset) {
+            Some(price) => price,
+            None => return sc_error!(ErrorCode::NotLiquidatable.error_message()),
+        };
+        let premium = BigUint::from(BASIS_POINTS + self.get_liquidation_premium());
+        let reimbursement = sc_try!(mul_div!(
+            amount.clone() * price,
+            premium,
+            BigUint::from(BASIS_POINTS),
+            Rounding::Down
+        ));
+        sc_try!(self.reimburse_liquidator(broker, &liquidator, reimbursement));
+
+        let final_position = self.calculate_position(broker, None);
+        require!(
+            final_position.state == PositionState::Ok
+                || final_position.weighted_collateral.clone() + initial_position.total_liabilities
+                    > initial_position.weighted_collateral + final_position.total_liabilities.clone(),
+            ErrorCode::LiquidationNotImproving
+        );
+        self.check_position(&liquidator, None)
     }
 
-    #[callback]
-    fn asset_withdrawl_callback(
-        &self,
-        call_result: AsyncCallResult<()>,
-        #[callback_arg] cb_asset_address: &Address,
-        #[callback_arg] cb_account_address: &Address,
-        #[callback_arg] cb_amount: BigUint,
-    ) -> elrond_wasm::SCResult<()>  {
-        if let AsyncCallResult::Ok(()) = call_result {
-            self.asset_withdrawl(cb_asset_address, cb_account_address, &cb_amount)
-        } else {
-            sc_error!("Error completing asset withdrawl")
+    // Can be called by the sender of the order or by one of their active delegates allowed to cancel
+    #[endpoint(cancelOrder)]
+    fn cancel_order(&self, order: &Order<BigUint>) -> SCResult<()> {
+        let caller = self.get_caller();
+        if caller != order.sender_address {
+            require!(
+                self.is_active_delegate(&order.sender_address, &caller)
+                    && self
+                        .get_delegate_permissions(&order.sender_address, &caller)
+                        .can_cancel(),
+                ErrorCode::NotOrderOwner
+            );
         }
+        self.perform_cancel_order(order, &order.sender_address)
     }
 
-    /*----------  internal  ----------*/
-
-    #[inline]
-    fn asset_deposit(
+    // Lets another key act for the caller until `expiration`. Registering a delegate again replaces its
+    // expiration and permissions.
+    #[endpoint(registerDelegate)]
+    fn register_delegate(
         &self,
-        asset_address: &Address,
-        account_address: &Address,
-        amount: &BigUint,
+        delegate_address: &Address,
+        expiration: u64,
+        permissions: DelegatePermissions,
     ) -> SCResult<()> {
-        let mut balance = self.get_asset_balance(asset_address, &account_address);
-        *balance += amount; // this will be safely updated after the function ends according to Elrond docs
-        self.events()
-            .new_asset_deposit(&account_address, asset_address, amount); // event
+        let caller = self.get_caller();
+        require_ne!(delegate_address, &caller, ErrorCode::SelfDelegation);
+        require!(expiration >= self.get_block_timestamp(), ErrorCode::ExpirationInPast);
+
+        self.set_delegate_expiration(&caller, delegate_address, expiration);
+        self.set_delegate_permissions(&caller, delegate_address, &permissions);
+
+        self.events().delegate_registered(
+            &caller,
+            delegate_address,
+            &Delegate {
+                expiration,
+                permissions,
+            },
+        );
         Ok(())
     }
 
-    #[inline]
+    #[endpoint(revokeDelegate)]
+    fn revoke_delegate(&self, delegate_address: &Address) -> SCResult<()> {
+        let caller = self.get_caller();
+        require!(
+            self.get_delegate_expiration(&caller, delegate_address) != 0,
+            ErrorCode::DelegateNotRegistered
+        );
+
+        self.set_delegate_expiration(&caller, delegate_address, 0);
+        self.set_delegate_permissions(&caller, delegate_address, &DelegatePermissions::Trade);
+
+        self.events().delegate_revoked(&caller, delegate_address);
+        Ok(())
+    }
+
+    /*----------  relayed (signed off-chain by the user)  ----------*/
+
+    #[endpoint(withdrawWithAuthorization)]
+    fn withdraw_with_authorization(
+        &self,
+        authorization: WithdrawalAuthorization<BigUint>,
+    ) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        sc_try!(self.use_authorization(&authorization));
+        self.request_withdraw(
+            &authorization.sender_address,
+            &authorization.asset_address,
+            &authorization.amount,
+        )
+    }
+
+    #[endpoint(cancelOrderWithAuthorization)]
+    fn cancel_order_with_authorization(
+        &self,
+        order: &Order<BigUint>,
+        authorization: CancellationAuthorization,
+    ) -> SCResult<()> {
+        let order_hash = sc_try!(self.hash_order(order));
+        require!(
+            authorization.order_hash == order_hash,
+            ErrorCode::AuthorizationMismatch
+        );
+        sc_try!(self.use_authorization(&authorization));
+        self.perform_cancel_order(order, &authorization.sender_address)
+    }
+
+    #[endpoint(internalTransferWithAuthorization)]
+    fn internal_transfer_with_authorization(
+        &self,
+        authorization: InternalTransferAuthorization<BigUint>,
+    ) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        sc_try!(self.use_authorization(&authorization));
+        self.asset_transfer(
+            &authorization.asset_address,
+            &authorization.sender_address,
+            &authorization.to,
+            &authorization.amount,
+        )
+    }
+
+    /*----------  forced withdrawals  ----------*/
+
+    // Escape hatch for when the matchers, a pause or the owner's limits freeze the exchange. Fills can still
+    // be settled against the balance during the delay, so at most the remaining balance is withdrawn.
+    // Each of the caller's sub-accounts has its own request, since transfers between them stop during a pause.
+    #[endpoint(requestForcedWithdrawal)]
+    fn request_forced_withdrawal(
+        &self,
+        asset_address: &Address,
+        sub_account: u64,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        let account = self.get_sub_account_address(&self.get_caller(), sub_account);
+        ensure_not_zero!(*amount);
+        require_eq!(
+            self.get_forced_withdrawal_amount(asset_address, &account),
+            BigUint::zero(),
+            ErrorCode::ForcedWithdrawalPending
+        );
+
+        let available_at = match self
+            .get_block_timestamp()
+            .checked_add(self.get_forced_withdrawal_delay())
+        {
+            Some(available_at) => available_at,
+            None => return sc_error!(ErrorCode::DelayOverflow.error_message()),
+        };
+        self.set_forced_withdrawal_amount(asset_address, &account, amount);
+        self.set_forced_withdrawal_available_at(asset_address, &account, available_at);
+
+        self.events()
+            .forced_withdrawal_requested(&account, asset_address, amount);
+        Ok(())
+    }
+
+    // Neither the daily caps nor the asset status apply, only the margin position of the sub-account is
+    // checked. The funds are sent to the caller.
+    #[endpoint(executeForcedWithdrawal)]
+    fn execute_forced_withdrawal(&self, asset_address: &Address, sub_account: u64) -> SCResult<()> {
+        let caller = self.get_caller();
+        let account = self.get_sub_account_address(&caller, sub_account);
+        let requested = self.get_forced_withdrawal_amount(asset_address, &account);
+        ensure_not_zero!(requested, ErrorCode::NoForcedWithdrawal);
+        require!(
+            self.get_block_timestamp() >= self.get_forced_withdrawal_available_at(asset_address, &account),
+            ErrorCode::ForcedWithdrawalNotAvailable
+        );
+
+        let balance = self.get_asset_balance(asset_address, &account).clone();
+        let amount = if requested < balance { requested } else { balance };
+
+        self.set_forced_withdrawal_amount(asset_address, &account, &BigUint::zero());
+        self.set_forced_withdrawal_available_at(asset_address, &account, 0);
+
+        self.events()
+            .forced_withdrawal_executed(&account, asset_address, &amount);
+        self.send_withdrawal(&account, &caller, asset_address, &amount)
+    }
+
+    // Withdraws a queued withdrawal once its delay has passed
+    #[endpoint(executeQueuedWithdrawal)]
+    fn execute_queued_withdrawal(&self, asset_address: &Address) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        let caller = self.get_caller();
+        let amount = self.get_queued_withdrawal_amount(asset_address, &caller);
+        ensure_not_zero!(amount, ErrorCode::NoQueuedWithdrawal);
+        require!(
+            self.get_block_timestamp() >= self.get_queued_withdrawal_available_at(asset_address, &caller),
+            ErrorCode::QueuedWithdrawalNotAvailable
+        );
+
+        require!(
+            self.get_effective_asset_status(asset_address).allows_withdrawals(),
+            ErrorCode::WithdrawalsDisabled
+        );
+        sc_try!(self.record_withdrawal(&caller, asset_address, &amount));
+
+        // the amount left the balance when it was queued, so it is paid straight from the queue and a
+        // liability opened in the meantime can't take it
+        self.set_queued_withdrawal_amount(asset_address, &caller, &BigUint::zero());
+        self.set_queued_withdrawal_available_at(asset_address, &caller, 0);
+        self.remove_withdrawl_from_totals(asset_address, &caller, &amount);
+        self.transfer_withdrawal(&caller, &caller, asset_address, &amount);
+        Ok(())
+    }
+
+    // Returns a queued withdrawal to the user's balance, called by the user or the guardian
+    #[endpoint(cancelQueuedWithdrawal)]
+    fn cancel_queued_withdrawal(&self, asset_address: &Address, user_address: &Address) -> SCResult<()> {
+        let caller = self.get_caller();
+        require!(
+            &caller == user_address || caller == self.get_guardian(),
+            ErrorCode::NotUserOrGuardian
+        );
+        let amount = self.get_queued_withdrawal_amount(asset_address, user_address);
+        ensure_not_zero!(amount, ErrorCode::NoQueuedWithdrawal);
+
+        self.set_queued_withdrawal_amount(asset_address, user_address, &BigUint::zero());
+        self.set_queued_withdrawal_available_at(asset_address, user_address, 0);
+        self.credit_balance(asset_address, user_address, &amount);
+
+        self.events()
+            .queued_withdrawal_cancelled(user_address, asset_address, &amount);
+        Ok(())
+    }
+
+    // Locks part of the balance of the staking asset of one of the caller's sub-accounts
+    #[endpoint]
+    fn stake(&self, sub_account: u64, amount: &BigUint) -> SCResult<()> {
+        require!(!self.is_paused(), ErrorCode::ExchangePaused);
+        ensure_not_zero!(*amount);
+        let staking_asset = self.get_staking_asset();
+        require!(staking_asset != Address::from(ERD_ASSET_ADDRESS), ErrorCode::StakingNotEnabled);
+
+        let account = self.get_sub_account_address(&self.get_caller(), sub_account);
+        {
+            let mut balance = self.get_asset_balance(&staking_asset, &account);
+            *balance = sc_try!(checked_sub!(*balance, *amount, ErrorCode::InsufficientBalance));
+        }
+        self.set_staked_amount(&account, &(self.get_staked_amount(&account) + amount.clone()));
+        self.set_total_staked(&(self.get_total_staked() + amount.clone()));
+        sc_try!(self.check_position(&account, None));
+
+        self.events().staked(&account, amount);
+        Ok(())
+    }
+
+    // Starts the unstake delay for `amount` of the stake. Requesting again while an unstake is pending adds
+    // to it and restarts the delay. Like forced withdrawals, unstaking works while the exchange is paused.
+    #[endpoint(requestUnstake)]
+    fn request_unstake(&self, sub_account: u64, amount: &BigUint) -> SCResult<()> {
+        ensure_not_zero!(*amount);
+
+        let account = self.get_sub_account_address(&self.get_caller(), sub_account);
+        let staked = sc_try!(checked_sub!(
+            self.get_staked_amount(&account),
+            *amount,
+            ErrorCode::InsufficientBalance
+        ));
+        self.set_staked_amount(&account, &staked);
+        self.set_unstaking_amount(&account, &(self.get_unstaking_amount(&account) + amount.clone()));
+        let available_at = match self.get_block_timestamp().checked_add(self.get_unstake_delay()) {
+            Some(available_at) => available_at,
+            None => return sc_error!(ErrorCode::DelayOverflow.error_message()),
+        };
+        self.set_unstake_available_at(&account, available_at);
+        sc_try!(self.check_position(&account, None));
+
+        self.events().unstake_requested(&account, amount);
+        Ok(())
+    }
+
+    // Returns the unstaked amount to the sub-account's balance once the delay has passed
+    #[endpoint(withdrawStake)]
+    fn withdraw_stake(&self, sub_account: u64) -> SCResult<()> {
+        let account = self.get_sub_account_address(&self.get_caller(), sub_account);
+        let amount = self.get_unstaking_amount(&account);
+        ensure_not_zero!(amount, ErrorCode::NoUnstake);
+        require!(
+            self.get_block_timestamp() >= self.get_unstake_available_at(&account),
+            ErrorCode::UnstakeNotAvailable
+        );
+
+        self.set_unstaking_amount(&account, &BigUint::zero());
+        self.set_unstake_available_at(&account, 0);
+        self.set_total_staked(&sc_try!(checked_sub!(self.get_total_staked(), amount)));
+        self.credit_balance(&self.get_staking_asset(), &account, &amount);
+
+        self.events().stake_withdrawn(&account, &amount);
+        Ok(())
+    }
+
+    // Moves the caller's broker fees in the asset to their balance
+    #[endpoint(claimBrokerFees)]
+    fn claim_broker_fees(&self, asset_address: &Address) -> SCResult<()> {
+        let caller = self.get_caller();
+        let amount = self.get_broker_fees(asset_address, &caller);
+        ensure_not_zero!(amount, ErrorCode::NoBrokerFees);
+
+        self.set_broker_fees(asset_address, &caller, &BigUint::zero());
+        self.credit_balance(asset_address, &caller, &amount);
+
+        self.events()
+            .broker_fees_claimed(&caller, asset_address, &amount);
+        Ok(())
+    }
+
+    /*----------  owner  ----------*/
+
+    #[endpoint]
+    fn pause(&self) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_paused(true);
+        Ok(())
+    }
+
+    #[endpoint]
+    fn unpause(&self) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(
+            self.get_user_balance_totals_seeding_count() == 0,
+            ErrorCode::UserBalanceTotalsSeeding
+        );
+        self.set_paused(false);
+        Ok(())
+    }
+
+    #[endpoint(setSelfTradePrevention)]
+    fn set_self_trade_prevention(&self, mode: SelfTradePrevention) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_self_trade_prevention_storage(&mode);
+        Ok(())
+    }
+
+    #[endpoint(setForcedWithdrawalDelay)]
+    fn set_forced_withdrawal_delay(&self, delay: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require_le!(delay, MAX_FORCED_WITHDRAWAL_DELAY, ErrorCode::DelayAboveMaximum);
+        self.set_forced_withdrawal_delay_storage(delay);
+        Ok(())
+    }
+
+    // Sends what the exchange holds beyond what it owes its users, which no user can withdraw. Refused while
+    // the user balance totals of the asset are incomplete, since the excess would include user funds.
+    #[endpoint(rescueExcess)]
+    fn rescue_excess(&self, asset_address: &Address, to: &Address) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        let excess = sc_try!(self.get_solvency(asset_address)).excess();
+        ensure_not_zero!(excess, ErrorCode::NoExcess);
+        if asset_address == &(ERD_ASSET_ADDRESS.into()) {
+            self.send_tx(to, &excess, "");
+            self.events().excess_rescued(asset_address, to, &excess);
+        } else {
+            // taken off the tracked holdings right away, a failed transfer puts it back
+            let holdings = self.get_token_holdings(asset_address);
+            self.set_token_holdings(asset_address, &solvency::saturating_sub(&holdings, &excess));
+            let token_contract = contract_proxy!(self, asset_address, TokenRescue);
+            token_contract.transfer(asset_address, to, excess.clone(), to, excess);
+        }
+        Ok(())
+    }
+
+    // For storage from before the user balance totals were kept, the owner seeds them in batches of users.
+    // The first batch resets the totals of the asset, then each user's balance, stake, queued withdrawal,
+    // broker fees and liability in the asset are added once. Seeding is done while the exchange is paused, so
+    // no fill moves funds between users that have been counted and users that haven't.
+    #[endpoint(seedUserBalanceTotals)]
+    fn seed_user_balance_totals(&self, asset_address: &Address, user_addresses: &Vec<Address>) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(
+            self.are_user_balance_totals_incomplete(asset_address),
+            ErrorCode::UserBalanceTotalsComplete
+        );
+        require!(self.is_paused(), ErrorCode::ExchangeNotPaused);
+        require!(!self.is_legacy_migration_open(), ErrorCode::LegacyMigrationOpen);
+
+        if !self.is_user_balance_totals_seeding(asset_address) {
+            self.set_user_balance_totals_seeding(asset_address, true);
+            self.set_user_balance_totals_seeding_count(self.get_user_balance_totals_seeding_count() + 1);
+            *self.get_total_user_balances(asset_address) = BigUint::zero();
+            *self.get_total_liabilities(asset_address) = BigUint::zero();
+        }
+
+        let staking_asset = self.get_staking_asset();
+        for user_address in user_addresses.iter() {
+            if self.is_user_balance_totals_seeded(asset_address, user_address) {
+                continue;
+            }
+            self.set_user_balance_totals_seeded(asset_address, user_address, true);
+
+            let mut user_balances = (*self.get_asset_balance(asset_address, user_address)).clone();
+            user_balances += &self.get_queued_withdrawal_amount(asset_address, user_address);
+            user_balances += &self.get_broker_fees(asset_address, user_address);
+            if asset_address == &staking_asset {
+                user_balances += &self.get_staked_amount(user_address);
+                user_balances += &self.get_unstaking_amount(user_address);
+            }
+            *self.get_total_user_balances(asset_address) += &user_balances;
+
+            let liabilities = self.get_liabilities(user_address);
+            if let Some(liability) = liabilities.iter().find(|liability| &liability.asset == asset_address) {
+                *self.get_total_liabilities(asset_address) += &liability.outstanding_amount;
+            }
+        }
+        Ok(())
+    }
+
+    // Marks the totals of the asset as complete, which enables its solvency checks and rescues. An asset
+    // nobody held before the upgrade can be completed without seeding, its totals are kept since.
+    #[endpoint(completeUserBalanceTotals)]
+    fn complete_user_balance_totals(&self, asset_address: &Address) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(
+            self.are_user_balance_totals_incomplete(asset_address),
+            ErrorCode::UserBalanceTotalsComplete
+        );
+        if self.is_user_balance_totals_seeding(asset_address) {
+            self.set_user_balance_totals_seeding(asset_address, false);
+            self.set_user_balance_totals_seeding_count(self.get_user_balance_totals_seeding_count() - 1);
+        }
+        self.set_user_balance_totals_completed(asset_address, true);
+        Ok(())
+    }
+
+    // Lists the asset, or updates the metadata and status of an asset that is already listed
+    #[endpoint(listAsset)]
+    fn list_asset(
+        &self,
+        asset_address: &Address,
+        ticker: Vec<u8>,
+        decimals: u8,
+        status: AssetStatus,
+    ) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        let mut listed_assets = self.get_listed_assets();
+        if !listed_assets.contains(asset_address) {
+            listed_assets.push(asset_address.clone());
+            self.set_listed_assets(&listed_assets);
+        }
+        self.set_asset_ticker(asset_address, &ticker);
+        self.set_asset_decimals(asset_address, decimals);
+        self.set_asset_status_storage(asset_address, &status);
+        Ok(())
+    }
+
+    // Delisted assets stay in the list so their metadata remains available
+    #[endpoint(setAssetStatus)]
+    fn set_asset_status(&self, asset_address: &Address, status: AssetStatus) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(self.get_listed_assets().contains(asset_address), ErrorCode::AssetNotListed);
+        self.set_asset_status_storage(asset_address, &status);
+        Ok(())
+    }
+
+    // Caps of 0 remove the cap and a threshold of 0 stops queueing withdrawals of the asset.
+    // Withdrawals only count towards a cap while it is set.
+    #[endpoint(setWithdrawalLimits)]
+    fn set_withdrawal_limits(
+        &self,
+        asset_address: &Address,
+        daily_cap: &BigUint,
+        user_daily_cap: &BigUint,
+        queue_threshold: &BigUint,
+    ) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_withdrawal_daily_cap(asset_address, daily_cap);
+        self.set_user_withdrawal_daily_cap(asset_address, user_daily_cap);
+        self.set_withdrawal_queue_threshold(asset_address, queue_threshold);
+        Ok(())
+    }
+
+    #[endpoint(setWithdrawalQueueDelay)]
+    fn set_withdrawal_queue_delay(&self, delay: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_withdrawal_queue_delay_storage(delay);
+        Ok(())
+    }
+
+    #[endpoint(setGuardian)]
+    fn set_guardian(&self, guardian_address: &Address) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_guardian_storage(guardian_address);
+        Ok(())
+    }
+
+    // Lowering the cap below the current total deposits only blocks new deposits
+    #[endpoint(setDepositCap)]
+    fn set_deposit_cap(&self, asset_address: &Address, cap: &BigUint) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_deposit_cap_storage(asset_address, cap);
+        Ok(())
+    }
+
+    // Shrinking the capacity takes effect for a pair the next time it trades
+    #[endpoint(setObservationCapacity)]
+    fn set_observation_capacity(&self, capacity: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        ensure_not_zero!(capacity);
+        self.set_observation_capacity_storage(capacity);
+        Ok(())
+    }
+
+    #[endpoint(setPriceProviders)]
+    fn set_price_providers(&self, provider_addresses: &Vec<Address>) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_price_providers_storage(provider_addresses);
+        Ok(())
+    }
+
+    #[endpoint(setOracleMode)]
+    fn set_oracle_mode(&self, mode: OracleMode, trusted_provider: &Address) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        if mode == OracleMode::TrustedFeed {
+            require!(
+                self.get_price_providers().contains(trusted_provider),
+                ErrorCode::UnknownPriceProvider
+            );
+        }
+        self.set_oracle_mode_storage(&mode);
+        self.set_trusted_price_provider_storage(trusted_provider);
+        Ok(())
+    }
+
+    // Accepts the asset for matcher fees, or updates its rate and discount if it already is
+    #[endpoint(setFeeAsset)]
+    fn set_fee_asset(&self, asset_address: &Address, rate: &BigUint, discount: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require_le!(discount, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+
+        let mut fee_assets = self.get_fee_assets();
+        if !fee_assets.contains(asset_address) {
+            fee_assets.push(asset_address.clone());
+            self.set_fee_assets(&fee_assets);
+        }
+        self.set_fee_asset_rate(asset_address, rate);
+        self.set_fee_asset_discount(asset_address, discount);
+        Ok(())
+    }
+
+    #[endpoint(removeFeeAsset)]
+    fn remove_fee_asset(&self, asset_address: &Address) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        let mut fee_assets = self.get_fee_assets();
+        fee_assets.retain(|fee_asset| fee_asset != asset_address);
+        self.set_fee_assets(&fee_assets);
+        self.set_fee_asset_rate(asset_address, &BigUint::zero());
+        self.set_fee_asset_discount(asset_address, 0);
+        Ok(())
+    }
+
+    #[endpoint(setMatcherFeeRate)]
+    fn set_matcher_fee_rate(&self, rate: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require_le!(rate, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+        self.set_matcher_fee_rate_storage(rate);
+        Ok(())
+    }
+
+    #[endpoint(setBrokerShare)]
+    fn set_broker_share(&self, broker_address: &Address, share: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require_le!(share, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+        self.set_broker_share_storage(broker_address, share);
+        Ok(())
+    }
+
+    #[endpoint(updateStakingSettings)]
+    fn update_staking_settings(
+        &self,
+        staking_asset: &Address,
+        unstake_delay: u64,
+        stake_risk: u64,
+    ) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require_le!(stake_risk, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+        require!(
+            *staking_asset == self.get_staking_asset() || self.get_total_staked() == BigUint::zero(),
+            ErrorCode::StakingAssetInUse
+        );
+        self.set_staking_asset(staking_asset);
+        self.set_unstake_delay(unstake_delay);
+        self.set_stake_risk(stake_risk);
+        Ok(())
+    }
+
+    #[endpoint(setFeeTiers)]
+    fn set_fee_tiers(&self, min_stakes: &Vec<BigUint>, discounts: &Vec<u64>) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(
+            min_stakes.len() == discounts.len(),
+            ErrorCode::LengthMismatch
+        );
+
+        let mut tiers: Vec<FeeTier<BigUint>> = Vec::new();
+        for (min_stake, discount) in min_stakes.iter().zip(discounts.iter()) {
+            require_le!(*discount, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+            if let Some(previous) = tiers.last() {
+                require!(previous.min_stake < *min_stake, ErrorCode::TiersNotSorted);
+            }
+            tiers.push(FeeTier {
+                min_stake: min_stake.clone(),
+                discount: *discount,
+            });
+        }
+        self.set_fee_tiers_storage(&tiers);
+        Ok(())
+    }
+
+    #[endpoint(updateMarginalSettings)]
+    fn update_marginal_settings(
+        &self,
+        collateral_assets: &Vec<Address>,
+        price_overdue: u64,
+        position_overdue: u64,
+    ) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        self.set_collateral_assets(collateral_assets);
+        self.set_price_overdue(price_overdue);
+        self.set_position_overdue(position_overdue);
+        Ok(())
+    }
+
+    #[endpoint(setLiquidationPremium)]
+    fn set_liquidation_premium(&self, premium: u64) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require_le!(premium, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+        self.set_liquidation_premium_storage(premium);
+        Ok(())
+    }
+
+    #[endpoint(updateAssetRisks)]
+    fn update_asset_risks(&self, asset_addresses: &Vec<Address>, risks: &Vec<u64>) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(
+            asset_addresses.len() == risks.len(),
+            ErrorCode::LengthMismatch
+        );
+        for (asset_address, risk) in asset_addresses.iter().zip(risks.iter()) {
+            require_le!(*risk, BASIS_POINTS, ErrorCode::AboveBasisPoints);
+            self.set_asset_risk(asset_address, *risk);
+        }
+        Ok(())
+    }
+
+    /*----------  upgrade  ----------*/
+
+    // Brings storage written by an older version of the contract up to date, one version at a time.
+    // To be called by the owner after the contract code has been upgraded. Storage written before versioning
+    // has no owner, init sets the account that upgrades the code as its owner.
+    #[endpoint]
+    fn upgrade(&self) -> SCResult<()> {
+        let mut version = self.get_storage_version();
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(version < CURRENT_STORAGE_VERSION, ErrorCode::StorageUpToDate);
+
+        while version < CURRENT_STORAGE_VERSION {
+            version += 1;
+            sc_try!(self.run_migration(version));
+        }
+        self.set_storage_version(version);
+        Ok(())
+    }
+
+    // Before storage version 1 fillOrders stored balances under (user_address, asset_address) instead
+    // of (asset_address, user_address). Map keys can't be enumerated on chain, so the owner passes the
+    // affected pairs in batches and they are moved to the correct key while the legacy migration is open.
+    #[endpoint(migrateSwappedBalances)]
+    fn migrate_swapped_balances(
+        &self,
+        asset_addresses: &Vec<Address>,
+        user_addresses: &Vec<Address>,
+    ) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(self.is_legacy_migration_open(), ErrorCode::NoLegacyMigration);
+        require!(
+            asset_addresses.len() == user_addresses.len(),
+            ErrorCode::LengthMismatch
+        );
+
+        for (asset_address, user_address) in asset_addresses.iter().zip(user_addresses.iter()) {
+            let legacy_balance = {
+                let mut swapped_balance = self.get_asset_balance(user_address, asset_address);
+                let legacy_balance = (*swapped_balance).clone();
+                *swapped_balance = BigUint::zero();
+                legacy_balance
+            };
+            let mut balance = self.get_asset_balance(asset_address, user_address);
+            *balance += &legacy_balance;
+        }
+        Ok(())
+    }
+
+    // Orders signed for the code from before storage versioning have no trigger, broker or sub-account fields,
+    // so their trades and status are stored under the hash of the old layout. The owner passes the orders
+    // that were filled or cancelled in batches and their state is moved to the hash of the same order in the
+    // current layout while the legacy migration is open.
+    #[endpoint(migrateLegacyOrders)]
+    fn migrate_legacy_orders(&self, legacy_orders: Vec<LegacyOrder<BigUint>>) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(self.is_legacy_migration_open(), ErrorCode::NoLegacyMigration);
+
+        for legacy_order in legacy_orders.into_iter() {
+            let legacy_hash = sc_try!(self.hash_legacy_order(&legacy_order));
+            let order_hash = sc_try!(self.hash_order(&legacy_order.into_order()));
+
+            let mut trades = self.get_order_trades(&legacy_hash);
+            if !trades.is_empty() {
+                trades.extend(self.get_order_trades(&order_hash));
+                self.set_order_trades(&order_hash, &trades);
+                self.set_order_trades(&legacy_hash, &Vec::new());
+            }
+
+            match self.get_order_status(&legacy_hash) {
+                OrderStatus::New => {}
+                legacy_status => {
+                    // an order already filled in the current layout keeps its status unless it was cancelled
+                    if let OrderStatus::New = self.get_order_status(&order_hash) {
+                        self.set_order_status(&order_hash, &legacy_status);
+                    } else if self.is_order_cancelled(&legacy_hash) && !self.is_order_cancelled(&order_hash) {
+                        self.set_order_status(&order_hash, &OrderStatus::PartiallyCancelled);
+                    }
+                    self.set_order_status(&legacy_hash, &OrderStatus::New);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Closes the legacy migration for good once everything has been moved
+    #[endpoint(finishLegacyMigration)]
+    fn finish_legacy_migration(&self) -> SCResult<()> {
+        sc_try!(self.abort_if_owner_not_caller());
+        require!(self.is_legacy_migration_open(), ErrorCode::NoLegacyMigration);
+        self.set_legacy_migration_open(false);
+        Ok(())
+    }
+
+    /*----------  callbacks (used internally)  ----------*/
+
+    #[callback]
+    fn asset_deposit_callback(
+        &self,
+        call_result: AsyncCallResult<()>,
+        #[callback_arg] cb_asset_address: &Address,
+        #[callback_arg] cb_account_address: &Address,
+        #[callback_arg] cb_amount: BigUint,
+    ) -> elrond_wasm::SCResult<()> {
+        if let AsyncCallResult::Ok(()) = call_result {
+            let holdings = self.get_token_holdings(cb_asset_address) + cb_amount.clone();
+            self.set_token_holdings(cb_asset_address, &holdings);
+            self.asset_deposit(cb_asset_address, cb_account_address, &cb_amount)
+        } else {
+            sc_error!(ErrorCode::TokenCallFailed.error_message())
+        }
+    }
+
+    #[callback]
+    fn asset_withdrawl_callback(
+        &self,
+        call_result: AsyncCallResult<()>,
+        #[callback_arg] cb_asset_address: &Address,
+        #[callback_arg] cb_account_address: &Address,
+        #[callback_arg] cb_amount: BigUint,
+    ) -> elrond_wasm::SCResult<()>  {
+        if let AsyncCallResult::Ok(()) = call_result {
+            let holdings = self.get_token_holdings(cb_asset_address);
+            self.set_token_holdings(cb_asset_address, &solvency::saturating_sub(&holdings, &cb_amount));
+        } else {
+            // the tokens never left the exchange, so the amount debited before the transfer is given back
+            self.restore_asset_withdrawl(cb_asset_address, cb_account_address, &cb_amount);
+        }
+        Ok(())
+    }
+
+    #[callback]
+    fn token_holdings_callback(
+        &self,
+        call_result: AsyncCallResult<BigUint>,
+        #[callback_arg] cb_asset_address: &Address,
+    ) -> elrond_wasm::SCResult<()> {
+        if let AsyncCallResult::Ok(holdings) = call_result {
+            self.set_token_holdings(cb_asset_address, &holdings);
+            self.set_token_holdings_timestamp(cb_asset_address, self.get_block_timestamp());
+            let solvency = sc_try!(self.get_solvency(cb_asset_address));
+            require!(solvency.is_solvent(), ErrorCode::Insolvent);
+            Ok(())
+        } else {
+            sc_error!(ErrorCode::TokenCallFailed.error_message())
+        }
+    }
+
+    // A failed transfer leaves the tokens with the exchange, so they can be rescued again
+    #[callback]
+    fn excess_rescue_callback(
+        &self,
+        call_result: AsyncCallResult<()>,
+        #[callback_arg] cb_asset_address: &Address,
+        #[callback_arg] cb_to: &Address,
+        #[callback_arg] cb_amount: BigUint,
+    ) -> elrond_wasm::SCResult<()> {
+        if let AsyncCallResult::Ok(()) = call_result {
+            self.events()
+                .excess_rescued(cb_asset_address, cb_to, &cb_amount);
+        } else {
+            let holdings = self.get_token_holdings(cb_asset_address) + cb_amount;
+            self.set_token_holdings(cb_asset_address, &holdings);
+        }
+        Ok(())
+    }
+
+    /*----------  internal  ----------*/
+
+    fn abort_if_owner_not_caller(&self) -> SCResult<()> {
+        ensure_caller!(self, self.get_owner(), ErrorCode::NotOwner);
+        Ok(())
+    }
+
+    // Registered migrations, each one upgrading global storage from `version - 1` to `version`
+    fn run_migration(&self, version: u32) -> SCResult<()> {
+        match version {
+            1 => {
+                // storage from before the escape hatch reads a zero delay, which would make forced withdrawals instant
+                if self.get_forced_withdrawal_delay() == 0 {
+                    self.set_forced_withdrawal_delay_storage(DEFAULT_FORCED_WITHDRAWAL_DELAY);
+                }
+                // balances stored under swapped keys are moved by `migrate_swapped_balances`
+                self.set_legacy_migration_open(true);
+                Ok(())
+            }
+            2 => {
+                if self.get_observation_capacity() == 0 {
+                    self.set_observation_capacity_storage(DEFAULT_OBSERVATION_CAPACITY);
+                }
+                Ok(())
+            }
+            3 => {
+                // zero overdue periods would make every price stale and every liability overdue
+                if self.get_price_overdue() == 0 {
+                    self.set_price_overdue(DEFAULT_PRICE_OVERDUE);
+                }
+                if self.get_position_overdue() == 0 {
+                    self.set_position_overdue(DEFAULT_POSITION_OVERDUE);
+                }
+                Ok(())
+            }
+            4 => {
+                if self.get_unstake_delay() == 0 {
+                    self.set_unstake_delay(DEFAULT_UNSTAKE_DELAY);
+                }
+                Ok(())
+            }
+            5 => {
+                if self.get_withdrawal_queue_delay() == 0 {
+                    self.set_withdrawal_queue_delay_storage(DEFAULT_WITHDRAWAL_QUEUE_DELAY);
+                }
+                Ok(())
+            }
+            6 => {
+                // the user balance totals start at zero and stay incomplete until the owner seeds them, since
+                // only a fresh deploy marks them complete
+                Ok(())
+            }
+            _ => sc_error!(ErrorCode::NoMigration.error_message()),
+        }
+    }
+
+    #[inline]
+    fn asset_deposit(
+        &self,
+        asset_address: &Address,
+        account_address: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        self.credit_balance(asset_address, account_address, amount);
+        self.set_total_deposits(asset_address, &(self.get_total_deposits(asset_address) + amount.clone()));
+        if self.counts_in_user_balance_totals(asset_address, account_address) {
+            *self.get_total_user_balances(asset_address) += amount;
+        }
+        self.events()
+            .new_asset_deposit(&account_address, asset_address, amount); // event
+        Ok(())
+    }
+
+    #[inline]
     fn asset_withdrawl(
         &self,
         asset_address: &Address,
         account_address: &Address,
         amount: &BigUint,
-    ) -> SCResult<()> {
-        let mut balance = self.get_asset_balance(asset_address, account_address);
-        *balance -= amount;
+    ) -> SCResult<()> {
+        {
+            let mut balance = self.get_asset_balance(asset_address, account_address);
+            *balance = sc_try!(checked_sub!(*balance, *amount, ErrorCode::InsufficientBalance));
+        }
+        self.remove_withdrawl_from_totals(asset_address, account_address, amount);
+        Ok(())
+    }
+
+    // For an amount that has already left the user's balance, like a queued withdrawal
+    fn remove_withdrawl_from_totals(
+        &self,
+        asset_address: &Address,
+        account_address: &Address,
+        amount: &BigUint,
+    ) {
+        let total_deposits = self.get_total_deposits(asset_address);
+        self.set_total_deposits(asset_address, &solvency::saturating_sub(&total_deposits, amount));
+        if self.counts_in_user_balance_totals(asset_address, account_address) {
+            let mut total_user_balances = self.get_total_user_balances(asset_address);
+            *total_user_balances = solvency::saturating_sub(&total_user_balances, amount);
+        }
         self.events()
             .new_asset_withdrawl(account_address, asset_address, amount);
+    }
+
+    fn restore_asset_withdrawl(
+        &self,
+        asset_address: &Address,
+        account_address: &Address,
+        amount: &BigUint,
+    ) {
+        self.credit_balance(asset_address, account_address, amount);
+        self.set_total_deposits(asset_address, &(self.get_total_deposits(asset_address) + amount.clone()));
+        if self.counts_in_user_balance_totals(asset_address, account_address) {
+            *self.get_total_user_balances(asset_address) += amount;
+        }
+    }
+
+    // Withdrawals above the asset's queue threshold are taken from the balance and wait in the queue,
+    // where the user or the guardian can cancel them, smaller ones are withdrawn right away
+    fn request_withdraw(
+        &self,
+        user_address: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        let threshold = self.get_withdrawal_queue_threshold(asset_address);
+        if threshold == 0 || *amount <= threshold {
+            return self.perform_withdraw(user_address, asset_address, amount);
+        }
+
+        require!(
+            self.get_effective_asset_status(asset_address).allows_withdrawals(),
+            ErrorCode::WithdrawalsDisabled
+        );
+        // a request above a daily cap could never be executed
+        let daily_cap = self.get_withdrawal_daily_cap(asset_address);
+        if daily_cap > 0 {
+            require_le!(*amount, daily_cap, ErrorCode::WithdrawalLimitExceeded);
+        }
+        let user_daily_cap = self.get_user_withdrawal_daily_cap(asset_address);
+        if user_daily_cap > 0 {
+            require_le!(*amount, user_daily_cap, ErrorCode::WithdrawalLimitExceeded);
+        }
+        require_eq!(
+            self.get_queued_withdrawal_amount(asset_address, user_address),
+            BigUint::zero(),
+            ErrorCode::QueuedWithdrawalPending
+        );
+        sc_try!(self.check_position(user_address, Some((asset_address, amount))));
+        {
+            let mut balance = self.get_asset_balance(asset_address, user_address);
+            *balance = sc_try!(checked_sub!(*balance, *amount, ErrorCode::InsufficientBalance));
+        }
+
+        let available_at = match self
+            .get_block_timestamp()
+            .checked_add(self.get_withdrawal_queue_delay())
+        {
+            Some(available_at) => available_at,
+            None => return sc_error!(ErrorCode::DelayOverflow.error_message()),
+        };
+        self.set_queued_withdrawal_amount(asset_address, user_address, amount);
+        self.set_queued_withdrawal_available_at(asset_address, user_address, available_at);
+
+        self.events()
+            .withdrawal_queued(user_address, asset_address, amount);
+        Ok(())
+    }
+
+    fn perform_withdraw(
+        &self,
+        user_address: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        require!(
+            self.get_effective_asset_status(asset_address).allows_withdrawals(),
+            ErrorCode::WithdrawalsDisabled
+        );
+        sc_try!(self.record_withdrawal(user_address, asset_address, amount));
+        self.send_withdrawal(user_address, user_address, asset_address, amount)
+    }
+
+    // Pays out a withdrawal the caps and the asset status have already been checked for, if they apply.
+    // The amount is taken from `account_address`, one of the recipient's sub-accounts.
+    fn send_withdrawal(
+        &self,
+        account_address: &Address,
+        recipient: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        sc_try!(self.check_position(account_address, Some((asset_address, amount))));
+
+        // debited before sending so the amount can't be spent again while a token transfer is in flight
+        sc_try!(self.asset_withdrawl(asset_address, account_address, amount));
+        self.transfer_withdrawal(account_address, recipient, asset_address, amount);
+        Ok(())
+    }
+
+    // Sends an amount already taken from `account_address` and the totals. A failed token transfer gives it
+    // back to the account's balance in the callback.
+    fn transfer_withdrawal(
+        &self,
+        account_address: &Address,
+        recipient: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    ) {
+        if asset_address == &(ERD_ASSET_ADDRESS.into()) {
+            // TODO: can this handle transaction failures?
+            self.send_tx(recipient, amount, "");
+        } else {
+            let token_contract = contract_proxy!(self, asset_address, TransferFrom);
+            token_contract.transfer(
+                asset_address,
+                account_address,
+                amount.clone(),
+                recipient,
+                amount.clone(),
+            );
+        }
+    }
+
+    // Counts the withdrawal towards the asset's daily caps that are set, failing if it would exceed one
+    fn record_withdrawal(
+        &self,
+        user_address: &Address,
+        asset_address: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        let today = self.get_block_timestamp() / SECONDS_PER_DAY;
+
+        let daily_cap = self.get_withdrawal_daily_cap(asset_address);
+        if daily_cap > 0 {
+            let mut withdrawn = amount.clone();
+            if self.get_withdrawal_day(asset_address) == today {
+                withdrawn += &self.get_withdrawn_in_day(asset_address);
+            }
+            require_le!(withdrawn, daily_cap, ErrorCode::WithdrawalLimitExceeded);
+            self.set_withdrawal_day(asset_address, today);
+            self.set_withdrawn_in_day(asset_address, &withdrawn);
+        }
+
+        let user_daily_cap = self.get_user_withdrawal_daily_cap(asset_address);
+        if user_daily_cap > 0 {
+            let mut withdrawn = amount.clone();
+            if self.get_user_withdrawal_day(asset_address, user_address) == today {
+                withdrawn += &self.get_user_withdrawn_in_day(asset_address, user_address);
+            }
+            require_le!(withdrawn, user_daily_cap, ErrorCode::WithdrawalLimitExceeded);
+            self.set_user_withdrawal_day(asset_address, user_address, today);
+            self.set_user_withdrawn_in_day(asset_address, user_address, &withdrawn);
+        }
+        Ok(())
+    }
+
+    // Every asset can be used while the owner hasn't listed any, afterwards unlisted assets count as delisted,
+    // which still lets users withdraw them
+    fn get_effective_asset_status(&self, asset_address: &Address) -> AssetStatus {
+        let listed_assets = self.get_listed_assets();
+        if listed_assets.is_empty() {
+            AssetStatus::Active
+        } else if listed_assets.contains(asset_address) {
+            self.get_asset_status(asset_address)
+        } else {
+            AssetStatus::Delisted
+        }
+    }
+
+    // Assets without a deposit cap accept any amount
+    fn check_deposit_cap(&self, asset_address: &Address, amount: &BigUint) -> SCResult<()> {
+        let cap = self.get_deposit_cap(asset_address);
+        if cap > 0 {
+            require_le!(
+                self.get_total_deposits(asset_address) + amount.clone(),
+                cap,
+                ErrorCode::DepositCapExceeded
+            );
+        }
+        Ok(())
+    }
+
+    fn perform_cancel_order(&self, order: &Order<BigUint>, user_address: &Address) -> SCResult<()> {
+        let order_hash = sc_try!(self.hash_order(order));
+        require_eq!(&order.sender_address, user_address, ErrorCode::NotOrderOwner);
+
+        require!(
+            !self.is_order_cancelled(&order_hash),
+            ErrorCode::OrderCancelled
+        );
+
+        let (total_filled, _) = sc_try!(self.get_filled_amounts(order));
+
+        if total_filled > 0 {
+            self.set_order_status(&order_hash, &OrderStatus::PartiallyCancelled)
+        } else {
+            self.set_order_status(&order_hash, &OrderStatus::Cancelled)
+        }
+
+        self.events().order_update(
+            &order_hash,
+            user_address,
+            &self.get_order_status(&order_hash),
+        );
+        Ok(())
+    }
+
+    // checks expiry, replay protection and signature of a relayed message, then burns its nonce
+    fn use_authorization(&self, authorization: &dyn SignedMessage) -> SCResult<()> {
+        let signer = authorization.signer();
+        require!(
+            authorization.expiration() >= self.get_block_timestamp(),
+            ErrorCode::AuthorizationExpired
+        );
+        require!(
+            !self.is_authorization_nonce_used(signer, authorization.nonce()),
+            ErrorCode::NonceAlreadyUsed
+        );
+
+        if let Result::Ok(payload) = authorization.signed_payload(&self.get_sc_address()) {
+            let payload_hash = self.keccak256(payload.as_slice());
+            require!(
+                self.verify_ed25519(signer.as_bytes(), &payload_hash[..], authorization.signature()),
+                ErrorCode::InvalidSignature
+            );
+        } else {
+            return sc_error!(ErrorCode::EncodingFailed.error_message());
+        }
+
+        self.set_authorization_nonce_used(signer, authorization.nonce(), true);
+        Ok(())
+    }
+
+    #[inline]
+    fn asset_transfer(
+        &self,
+        asset_address: &Address,
+        from: &Address,
+        to: &Address,
+        amount: &BigUint,
+    ) -> SCResult<()> {
+        {
+            let mut from_balance = self.get_asset_balance(asset_address, from);
+            *from_balance = sc_try!(checked_sub!(*from_balance, *amount, ErrorCode::InsufficientBalance));
+        } // saved before the recipient balance is loaded, so from == to is safe
+        sc_try!(self.check_position(from, None));
+        self.credit_balance(asset_address, to, amount);
+        self.events()
+            .new_internal_transfer(from, to, asset_address, amount);
         Ok(())
     }
 
+    // the hash the order is signed with, which also keys its trades and status
     #[inline]
     fn hash_order(&self, order: &Order<BigUint>) -> SCResult<H256> {
+        if let Result::Ok(payload) = order.signed_payload(&self.get_sc_address()) {
+            Ok(self.keccak256(payload.as_slice()).into())
+        } else {
+            sc_error!(ErrorCode::EncodingFailed.error_message())
+        }
+    }
+
+    fn check_order_signature(&self, order: &Order<BigUint>, order_hash: &H256) -> SCResult<()> {
+        order.validate(
+            order_hash,
+            &|signer: &Address, hash: &H256, signature: &[u8]| {
+                self.verify_ed25519(signer.as_bytes(), hash.as_bytes(), signature)
+            },
+            &|user_address: &Address, delegate_address: &Address| {
+                self.is_active_delegate(user_address, delegate_address)
+                    && self
+                        .get_delegate_permissions(user_address, delegate_address)
+                        .can_trade()
+            },
+        )
+    }
+
+    #[inline]
+    fn hash_legacy_order(&self, order: &LegacyOrder<BigUint>) -> SCResult<H256> {
         if let Result::Ok(order_bytes) = order.top_encode() {
             Ok(self.keccak256(order_bytes.as_slice()).into())
         } else {
-            sc_error!("Error serializing order")
+            sc_error!(ErrorCode::EncodingFailed.error_message())
+        }
+    }
+
+    // A failed transaction would roll the cancellation back too, so in the cancel modes the fill
+    // is skipped and the transaction succeeds
+    fn prevent_self_trade(&self, buy_order: &Order<BigUint>, sell_order: &Order<BigUint>) -> SCResult<()> {
+        let mode = self.get_self_trade_prevention();
+        require!(mode != SelfTradePrevention::Reject, ErrorCode::SelfTrade);
+
+        // the age of an order comes from its nonce, on a tie the sell order counts as the newer one
+        let buy_is_older = buy_order.nonce <= sell_order.nonce;
+        let cancel_buy_order = match mode {
+            SelfTradePrevention::CancelOlder => buy_is_older,
+            _ => !buy_is_older,
+        };
+        let cancelled_order = if cancel_buy_order { buy_order } else { sell_order };
+
+        sc_try!(self.perform_cancel_order(cancelled_order, &cancelled_order.sender_address));
+        let cancelled_order_hash = sc_try!(self.hash_order(cancelled_order));
+        self.events().self_trade_prevented(
+            &cancelled_order.sender_address,
+            &cancelled_order_hash,
+            &mode,
+        );
+        Ok(())
+    }
+
+    fn current_price_cumulative(&self, base_asset: &Address, quote_asset: &Address, timestamp: u64) -> BigUint {
+        let mut price_cumulative = self.get_price_cumulative_last(base_asset, quote_asset);
+        let last_trade_timestamp = self.get_last_trade_timestamp(base_asset, quote_asset);
+        if timestamp > last_trade_timestamp {
+            let elapsed = BigUint::from(timestamp - last_trade_timestamp);
+            price_cumulative += &(self.get_last_price(base_asset, quote_asset) * elapsed);
+        }
+        price_cumulative
+    }
+
+    // Accrues the previous last price for the time since the last trade, must run before the last price is updated
+    fn update_price_oracle(&self, base_asset: &Address, quote_asset: &Address) {
+        let now = self.get_block_timestamp();
+        let price_cumulative = self.current_price_cumulative(base_asset, quote_asset, now);
+        self.set_price_cumulative_last(base_asset, quote_asset, &price_cumulative);
+        self.set_last_trade_timestamp(base_asset, quote_asset, now);
+
+        let mut observations = self.get_price_observations(base_asset, quote_asset);
+        twap::record_observation(&mut observations, self.get_observation_capacity(), now, &price_cumulative);
+        self.set_price_observations(base_asset, quote_asset, &observations);
+    }
+
+    fn record_pair_trade(
+        &self,
+        base_asset: &Address,
+        quote_asset: &Address,
+        price: &BigUint,
+        base_amount: &BigUint,
+        quote_amount: &BigUint,
+    ) {
+        let mut buckets = self.get_pair_stats_buckets(base_asset, quote_asset);
+        pair_stats::record_trade(&mut buckets, self.get_block_timestamp(), price, base_amount, quote_amount);
+        self.set_pair_stats_buckets(base_asset, quote_asset, &buckets);
+    }
+
+    // A conditional order that has already been partially filled stays triggered even if the price moves back
+    fn check_triggered(&self, order: &Order<BigUint>, order_hash: &H256) -> SCResult<()> {
+        let last_price = self.get_last_price(&order.base_asset, &order.quote_asset);
+        require!(
+            order.is_triggered(&last_price) || !self.get_order_trades(order_hash).is_empty(),
+            ErrorCode::OrderNotTriggered
+        );
+        Ok(())
+    }
+
+    // Fee assets are only restricted once the owner has accepted at least one
+    fn check_matcher_fee(&self, order: &Order<BigUint>) -> SCResult<()> {
+        let fee_assets = self.get_fee_assets();
+        if fee_assets.is_empty() {
+            return Ok(());
+        }
+        require!(
+            fee_assets.contains(&order.matcher_fee_asset),
+            ErrorCode::FeeAssetNotAccepted
+        );
+        let required_fee = sc_try!(self.get_required_matcher_fee(order));
+        require_le!(required_fee, order.matcher_fee, ErrorCode::FeeTooLow);
+        Ok(())
+    }
+
+    // Pro-rata share of the order's matcher fee, after the stake discount of the sub-account it trades for,
+    // for a fill, rounded down. The fill that completes the order collects whatever is left instead, so the
+    // fees paid add up to exactly the discounted `matcher_fee`. If the discount grew since earlier fills
+    // nothing is left to pay.
+    fn calculate_fill_fee(
+        &self,
+        order: &Order<BigUint>,
+        account_address: &Address,
+        filled_amount: &BigUint,
+    ) -> SCResult<BigUint> {
+        let discount = self.get_fee_discount(account_address);
+        let matcher_fee = sc_try!(mul_div!(
+            order.matcher_fee,
+            BigUint::from(BASIS_POINTS - discount),
+            BigUint::from(BASIS_POINTS),
+            Rounding::Down
+        ));
+
+        let (total_filled, total_fees_paid) = sc_try!(self.get_filled_amounts(order));
+        if &total_filled + filled_amount == order.amount {
+            if total_fees_paid < matcher_fee {
+                Ok(matcher_fee - total_fees_paid)
+            } else {
+                Ok(BigUint::zero())
+            }
+        } else {
+            mul_div!(matcher_fee, *filled_amount, order.amount, Rounding::Down)
         }
     }
 
+    // What the user receives is credited first, so it can cover the fee without opening a liability.
     #[inline]
     fn update_order_balance(
         &self,
+        user: &Address,
         order: Order<BigUint>,
         filled_amount: BigUint,
         amount_quote: BigUint,
-        is_buyer: bool,
+        matcher_fee: BigUint,
     ) -> SCResult<()> {
-        let user = order.sender_address;
-        let matcher_fee =
-            order.matcher_fee * filled_amount.clone() / order.amount; // TODO: Check how these operations are handled
+        let is_buyer = order.side == OrderSide::Buy;
 
-        {
-            let mut quote_asset_balance = self.get_asset_balance(&user, &order.quote_asset);
-            let mut base_asset_balance = self.get_asset_balance(&user, &order.base_asset);
+        if is_buyer {
+            self.credit_balance(&order.base_asset, user, &filled_amount);
+            self.debit_balance(&order.quote_asset, user, &amount_quote);
+        } else {
+            self.credit_balance(&order.quote_asset, user, &amount_quote);
+            self.debit_balance(&order.base_asset, user, &filled_amount);
+        }
+
+        // deduct the fees and transfer to matcher
+        self.debit_balance(&order.matcher_fee_asset, user, &matcher_fee);
+        self.distribute_fee(&order, &matcher_fee)
+    }
+
+    // Splits a fee between the broker of the order, who can claim their share later, and the matcher.
+    // Orders without a broker have the zero address as broker_address and the matcher gets the whole fee.
+    fn distribute_fee(&self, order: &Order<BigUint>, fee: &BigUint) -> SCResult<()> {
+        if *fee == 0 {
+            return Ok(());
+        }
+
+        let mut matcher_fee = fee.clone();
+        if order.broker_address != Address::from(ERD_ASSET_ADDRESS) {
+            let share = BigUint::from(self.get_broker_share(&order.broker_address));
+            // the broker's share rounds down, the remainder goes to the matcher
+            let broker_fee = sc_try!(mul_div!(*fee, share, BigUint::from(BASIS_POINTS), Rounding::Down));
+            if broker_fee > 0 {
+                matcher_fee -= &broker_fee;
+                let accrued =
+                    self.get_broker_fees(&order.matcher_fee_asset, &order.broker_address) + broker_fee.clone();
+                self.set_broker_fees(&order.matcher_fee_asset, &order.broker_address, &accrued);
+                self.events().broker_fee_accrued(
+                    &order.broker_address,
+                    &order.matcher_fee_asset,
+                    &broker_fee,
+                );
+            }
+        }
+
+        self.credit_balance(&order.matcher_fee_asset, &order.matcher_address, &matcher_fee);
+        self.events().matcher_fee_paid(
+            &order.matcher_address,
+            &order.matcher_fee_asset,
+            &matcher_fee,
+        );
+        Ok(())
+    }
+
+    // Adds to the user's balance, repaying their liability in the asset first. What is repaid was owed to
+    // other users, so it comes off both the user balance and the liability totals.
+    fn credit_balance(&self, asset_address: &Address, user_address: &Address, amount: &BigUint) {
+        let mut remaining = amount.clone();
 
-            if is_buyer {
-                *quote_asset_balance -= amount_quote;
-                *base_asset_balance += filled_amount;
+        let mut liabilities = self.get_liabilities(user_address);
+        if let Some(index) = liabilities.iter().position(|liability| &liability.asset == asset_address) {
+            let repaid = if remaining < liabilities[index].outstanding_amount {
+                liabilities[index].outstanding_amount -= &remaining;
+                core::mem::replace(&mut remaining, BigUint::zero())
             } else {
-                *quote_asset_balance += amount_quote;
-                *base_asset_balance -= filled_amount;
+                remaining -= &liabilities[index].outstanding_amount;
+                liabilities.remove(index).outstanding_amount
+            };
+            self.set_liabilities(user_address, &liabilities);
+            if self.counts_in_user_balance_totals(asset_address, user_address) {
+                self.remove_from_user_balance_totals(asset_address, &repaid);
             }
-        } // balances updated when scope ends
+        }
 
-        // deduct the fees and transfer to matcher
-        {
-            let mut matcher_fee_asset_balance =
-                self.get_asset_balance(&user, &order.matcher_fee_asset);
-            *matcher_fee_asset_balance -= matcher_fee;
+        if remaining > 0 {
+            let mut balance = self.get_asset_balance(asset_address, user_address);
+            *balance += &remaining;
+        }
+    }
+
+    // Takes from the user's balance, opening or growing a liability for whatever the balance doesn't cover.
+    // The shortfall is credited to other users, so it adds to both the user balance and the liability totals.
+    // The caller is responsible for checking the position.
+    fn debit_balance(&self, asset_address: &Address, user_address: &Address, amount: &BigUint) {
+        let shortfall = {
+            let mut balance = self.get_asset_balance(asset_address, user_address);
+            if *balance >= *amount {
+                *balance -= amount;
+                return;
+            }
+            let shortfall = amount.clone() - (*balance).clone();
+            *balance = BigUint::zero();
+            shortfall
+        };
+
+        if self.counts_in_user_balance_totals(asset_address, user_address) {
+            *self.get_total_user_balances(asset_address) += &shortfall;
+            *self.get_total_liabilities(asset_address) += &shortfall;
+        }
+
+        let mut liabilities = self.get_liabilities(user_address);
+        match liabilities.iter_mut().find(|liability| &liability.asset == asset_address) {
+            Some(liability) => liability.outstanding_amount += &shortfall,
+            None => liabilities.push(Liability {
+                asset: asset_address.clone(),
+                timestamp: self.get_block_timestamp(),
+                outstanding_amount: shortfall,
+            }),
+        }
+        self.set_liabilities(user_address, &liabilities);
+    }
+
+    // While the totals of the asset are being seeded only the changes of users already counted go into them
+    fn counts_in_user_balance_totals(&self, asset_address: &Address, user_address: &Address) -> bool {
+        !self.is_user_balance_totals_seeding(asset_address)
+            || self.is_user_balance_totals_seeded(asset_address, user_address)
+    }
+
+    fn remove_from_user_balance_totals(&self, asset_address: &Address, repaid: &BigUint) {
+        let mut total_user_balances = self.get_total_user_balances(asset_address);
+        *total_user_balances = solvency::saturating_sub(&total_user_balances, repaid);
+        let mut total_liabilities = self.get_total_liabilities(asset_address);
+        *total_liabilities = solvency::saturating_sub(&total_liabilities, repaid);
+    }
+
+    // Price of one unit of the asset in ERD, the unit positions are valued in, missing if the oracle has no fresh price
+    fn get_asset_price(&self, asset_address: &Address) -> Option<BigUint> {
+        if asset_address == &(ERD_ASSET_ADDRESS.into()) {
+            return Some(BigUint::from(1u64));
+        }
+        self.get_oracle_price(asset_address).map(|(price, _)| price)
+    }
+
+    // Returns (price, timestamp) from the provider prices that are younger than the price overdue. In the
+    // Median mode the timestamp is that of the oldest price taken into account.
+    fn get_oracle_price(&self, asset_address: &Address) -> Option<(BigUint, u64)> {
+        let now = self.get_block_timestamp();
+        let price_overdue = self.get_price_overdue();
+        let is_fresh = |timestamp: u64| timestamp > 0 && timestamp + price_overdue >= now;
+
+        match self.get_oracle_mode() {
+            OracleMode::TrustedFeed => {
+                let provider_address = self.get_trusted_price_provider();
+                let timestamp = self.get_provider_price_timestamp(asset_address, &provider_address);
+                if !is_fresh(timestamp) {
+                    return None;
+                }
+                Some((self.get_provider_price(asset_address, &provider_address), timestamp))
+            }
+            OracleMode::Median => {
+                let mut prices = Vec::new();
+                let mut oldest_timestamp = now;
+                for provider_address in self.get_price_providers().iter() {
+                    let timestamp = self.get_provider_price_timestamp(asset_address, provider_address);
+                    if is_fresh(timestamp) {
+                        prices.push(self.get_provider_price(asset_address, provider_address));
+                        oldest_timestamp = core::cmp::min(oldest_timestamp, timestamp);
+                    }
+                }
+                oracle::median(prices).map(|price| (price, oldest_timestamp))
+            }
+        }
+    }
+
+    // Position of the user, optionally as if `withdrawal` had already been taken from their balance
+    fn calculate_position(
+        &self,
+        user_address: &Address,
+        withdrawal: Option<(&Address, &BigUint)>,
+    ) -> Position<BigUint> {
+        let now = self.get_block_timestamp();
+        let mut weighted_collateral = BigUint::zero();
+        let mut total_liabilities = BigUint::zero();
+        let mut no_price = false;
+        let mut overdue = false;
+
+        for asset_address in self.get_collateral_assets().iter() {
+            let mut balance = self.get_asset_balance(asset_address, user_address).clone();
+            if let Some((withdrawn_asset, withdrawn_amount)) = withdrawal {
+                if withdrawn_asset == asset_address {
+                    balance = if balance > *withdrawn_amount {
+                        balance - withdrawn_amount.clone()
+                    } else {
+                        BigUint::zero()
+                    };
+                }
+            }
+            if balance == 0 {
+                continue;
+            }
+
+            match self.get_asset_price(asset_address) {
+                Some(price) => {
+                    let risk = BigUint::from(self.get_asset_risk(asset_address));
+                    weighted_collateral += &(balance * price * risk / BigUint::from(BASIS_POINTS));
+                }
+                None => no_price = true,
+            }
+        }
+
+        let staked = self.get_staked_amount(user_address);
+        if staked > 0 {
+            match self.get_asset_price(&self.get_staking_asset()) {
+                Some(price) => {
+                    let risk = BigUint::from(self.get_stake_risk());
+                    weighted_collateral += &(staked * price * risk / BigUint::from(BASIS_POINTS));
+                }
+                None => no_price = true,
+            }
+        }
+
+        let position_overdue = self.get_position_overdue();
+        for liability in self.get_liabilities(user_address).iter() {
+            match self.get_asset_price(&liability.asset) {
+                Some(price) => total_liabilities += &(liability.outstanding_amount.clone() * price),
+                None => no_price = true,
+            }
+            if liability.timestamp + position_overdue < now {
+                overdue = true;
+            }
+        }
+
+        let state = if no_price {
+            PositionState::NoPrice
+        } else if weighted_collateral < total_liabilities {
+            PositionState::Negative
+        } else if overdue {
+            PositionState::Overdue
+        } else {
+            PositionState::Ok
+        };
+
+        Position {
+            state,
+            weighted_collateral,
+            total_liabilities,
+        }
+    }
+
+    // Moves collateral worth `value` in ERD from the broker to the liquidator, one collateral asset after another
+    fn reimburse_liquidator(&self, broker: &Address, liquidator: &Address, value: BigUint) -> SCResult<()> {
+        let mut remaining_value = value;
+
+        for asset_address in self.get_collateral_assets().iter() {
+            if remaining_value == 0 {
+                break;
+            }
+            let price = match self.get_asset_price(asset_address) {
+                Some(price) => price,
+                None => continue,
+            };
+
+            let seized_amount = {
+                let mut broker_balance = self.get_asset_balance(asset_address, broker);
+                if *broker_balance == 0 {
+                    continue;
+                }
+                // rounded up so the liquidator is never paid less than the premium
+                let needed = sc_try!(mul_div!(
+                    remaining_value,
+                    BigUint::from(1u64),
+                    price,
+                    Rounding::Up
+                ));
+                let seized_amount = if needed < *broker_balance {
+                    needed
+                } else {
+                    (*broker_balance).clone()
+                };
+                *broker_balance -= &seized_amount;
+                seized_amount
+            };
+
+            let seized_value = seized_amount.clone() * price;
+            remaining_value = if seized_value < remaining_value {
+                remaining_value - seized_value
+            } else {
+                BigUint::zero()
+            };
+
+            self.credit_balance(asset_address, liquidator, &seized_amount);
+            self.events()
+                .collateral_seized(broker, liquidator, asset_address, &seized_amount);
         }
-        // TODO: Implement transfer of fees to matcher once there is a nicer tranfer function
 
+        require!(remaining_value == 0, ErrorCode::InsufficientCollateral);
+        Ok(())
+    }
+
+    // Accounts without liabilities are always healthy, so only borrowers pay for the position calculation
+    fn check_position(
+        &self,
+        user_address: &Address,
+        withdrawal: Option<(&Address, &BigUint)>,
+    ) -> SCResult<()> {
+        if self.get_liabilities(user_address).is_empty() {
+            return Ok(());
+        }
+        let position = self.calculate_position(user_address, withdrawal);
+        require!(position.state == PositionState::Ok, ErrorCode::UnhealthyPosition);
         Ok(())
     }
 
@@ -341,12 +2670,12 @@ pub trait OrionExchange {
         order: Order<BigUint>,
         filled_amount: BigUint,
         filled_price: BigUint,
+        matcher_fee: BigUint,
     ) -> SCResult<()> {
-        let matcher_fee = order.matcher_fee.clone() * filled_amount.clone() / order.amount.clone(); // TODO: Check how these operations are handled
         let (total_filled, total_fees_paid) = sc_try!(self.get_filled_amounts(&order));
 
-        require!(&total_filled + &filled_amount <= order.amount, "E3");
-        require!(&total_fees_paid + &matcher_fee <= order.matcher_fee, "E3");
+        require_le!(&total_filled + &filled_amount, order.amount, ErrorCode::Overfill);
+        require_le!(&total_fees_paid + &matcher_fee, order.matcher_fee, ErrorCode::FeeOverCap);
 
         let status = if total_filled.clone() + filled_amount.clone() < order.amount
             && self.get_order_trades(&order_hash).len() > 1
@@ -380,6 +2709,24 @@ pub trait OrionExchange {
     #[module(EventsModuleImpl)]
     fn events(&self) -> EventsModuleImpl<T, BigInt, BigUint>;
 
+    // Runs when the contract is deployed and again whenever its code is upgraded, which is done with
+    // `upgrading` set so that the storage is left for `upgrade` to migrate. Storage written before
+    // versioning has no owner yet and gets the account upgrading the code.
     #[init]
-    fn init(&self) {}
+    fn init(&self, upgrading: bool) {
+        if self.get_owner().is_zero() {
+            self.set_owner(&self.get_caller());
+        }
+        if upgrading || self.get_storage_version() > 0 {
+            return;
+        }
+        self.set_forced_withdrawal_delay_storage(DEFAULT_FORCED_WITHDRAWAL_DELAY);
+        self.set_observation_capacity_storage(DEFAULT_OBSERVATION_CAPACITY);
+        self.set_price_overdue(DEFAULT_PRICE_OVERDUE);
+        self.set_position_overdue(DEFAULT_POSITION_OVERDUE);
+        self.set_unstake_delay(DEFAULT_UNSTAKE_DELAY);
+        self.set_withdrawal_queue_delay_storage(DEFAULT_WITHDRAWAL_QUEUE_DELAY);
+        self.set_user_balance_totals_complete(true);
+        self.set_storage_version(CURRENT_STORAGE_VERSION);
+    }
 }
@@ -0,0 +1,37 @@
+use codec_derive::{Decode, Encode};
+
+imports!();
+
+// Asset risks and the liquidation premium are in basis points,
+// a risk of BASIS_POINTS counts the full value of the collateral
+pub static BASIS_POINTS: u64 = 10_000;
+
+/**
+ * Amount a user owes the exchange in an asset, created when a fill is settled against a balance that
+ * is too small. The timestamp is when the liability was opened and is kept until it is fully repaid.
+ */
+#[derive(Clone, Encode, Decode)]
+pub struct Liability<BigUint: BigUintApi> {
+    pub asset: Address,
+    pub timestamp: u64,
+    pub outstanding_amount: BigUint,
+}
+
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub enum PositionState {
+    Ok,
+    Negative,
+    Overdue,
+    NoPrice,
+}
+
+/**
+ * Margin position of a user, valued in ERD. The weighted position is `weighted_collateral - total_liabilities`
+ * and is negative exactly when the state is Negative.
+ */
+#[derive(Encode, Decode)]
+pub struct Position<BigUint: BigUintApi> {
+    pub state: PositionState,
+    pub weighted_collateral: BigUint,
+    pub total_liabilities: BigUint,
+}
@@ -0,0 +1,59 @@
+use codec_derive::{Decode, Encode};
+use elrond_codec::*;
+
+imports!();
+
+// continues the message tags of authorization.rs so a price can never be replayed as an authorization
+static PRICE_FEED_TAG: u8 = 3;
+
+/**
+ * How the oracle price of an asset is derived from the prices submitted by the providers
+ */
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub enum OracleMode {
+    Median,
+    TrustedFeed,
+}
+
+/**
+ * Price of one unit of an asset in ERD, signed off-chain by a price provider so that any account can relay it.
+ *
+ * The signed payload is built the same way as for authorizations: the exchange address, the price
+ * feed tag and every field except the signature, hashed with keccak256.
+ */
+#[derive(Encode, Decode)]
+pub struct PriceFeed<BigUint: BigUintApi> {
+    pub provider_address: Address,
+    pub asset_address: Address,
+    pub price: BigUint,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+impl<BigUint: BigUintApi> PriceFeed<BigUint> {
+    pub fn signed_payload(&self, exchange_address: &Address) -> Result<Vec<u8>, EncodeError> {
+        let mut payload = Vec::new();
+        exchange_address.dep_encode_to(&mut payload)?;
+        PRICE_FEED_TAG.dep_encode_to(&mut payload)?;
+        self.provider_address.dep_encode_to(&mut payload)?;
+        self.asset_address.dep_encode_to(&mut payload)?;
+        self.price.dep_encode_to(&mut payload)?;
+        self.timestamp.dep_encode_to(&mut payload)?;
+        Result::Ok(payload)
+    }
+}
+
+// Median of the prices, the mean of the two middle ones for an even count
+pub fn median<BigUint: BigUintApi>(mut prices: Vec<BigUint>) -> Option<BigUint> {
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort();
+
+    let middle = prices.len() / 2;
+    if prices.len() % 2 == 1 {
+        Some(prices[middle].clone())
+    } else {
+        Some((prices[middle - 1].clone() + prices[middle].clone()) / BigUint::from(2u64))
+    }
+}
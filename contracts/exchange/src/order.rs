@@ -1,48 +1,61 @@
-use common::require;
+use codec_derive::{Decode, Encode};
+use common::{require, require_le, ErrorCode, ErrorMessage};
 use elrond_codec::*;
 
 imports!();
 
-static INVALID_ORDER: &str = "Invalid Order Info";
-static ORDER_CANCELLED_OR_EXPIRED: &str = "Order cancelled or expired";
+// continues the message tags of authorization.rs and oracle.rs so an order can never be replayed as another message
+static ORDER_TAG: u8 = 4;
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Encode, Decode)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
-impl OrderSide {
-    pub fn to_u8(&self) -> u8 {
-        match self {
-            OrderSide::Buy => 0,
-            OrderSide::Sell => 1,
-        }
-    }
-
-    fn from_u8(v: u8) -> Result<Self, DecodeError> {
-        match v {
-            0 => Result::Ok(OrderSide::Buy),
-            1 => Result::Ok(OrderSide::Sell),
-            _ => Result::Err(DecodeError::InvalidValue),
-        }
-    }
+// Which way the last trade price must cross the trigger price for a conditional order to become fillable
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub enum TriggerDirection {
+    Above,
+    Below,
 }
 
-impl Encode for OrderSide {
-    fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
-        self.to_u8().dep_encode_to(dest)
-    }
-}
-
-impl Decode for OrderSide {
-    fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
-        OrderSide::from_u8(u8::dep_decode(input)?)
-    }
+/**
+ * An order signed off-chain by its sender or by one of their delegates.
+ *
+ * The order hash is the keccak256 of the exchange address, the order tag and every field except the signer
+ * and the signature. The hash is what the signer signs and it keys the order's trades and status, so adding
+ * a field changes the hash of every order. Orders from before the trigger, broker, sub-account and signer
+ * fields are decoded as `LegacyOrder`.
+ */
+#[derive(Clone, Encode, Decode)]
+pub struct Order<BigUint: BigUintApi> {
+    pub sender_address: Address,
+    pub matcher_address: Address,
+    pub base_asset: Address,
+    pub quote_asset: Address,
+    pub matcher_fee_asset: Address,
+    pub amount: BigUint,
+    pub price: BigUint,
+    pub matcher_fee: BigUint,
+    pub nonce: BigUint,
+    pub expiration: u64,
+    pub side: OrderSide,
+    pub trigger_price: BigUint,
+    pub trigger_direction: TriggerDirection,
+    pub broker_address: Address,
+    pub sub_account: u64,
+    pub signer_address: Address,
+    pub signature: Vec<u8>,
 }
 
-#[derive(Clone)]
-pub struct Order<BigUint: BigUintApi> {
+/**
+ * An order in the layout from before storage versioning, without the trigger, broker, sub-account and signer
+ * fields.
+ * The trades and status of such orders are stored under the hash of this layout until they are migrated.
+ */
+#[derive(Clone, Encode, Decode)]
+pub struct LegacyOrder<BigUint: BigUintApi> {
     pub sender_address: Address,
     pub matcher_address: Address,
     pub base_asset: Address,
@@ -57,8 +70,34 @@ pub struct Order<BigUint: BigUintApi> {
     pub signature: H256,
 }
 
-impl<BigUint: BigUintApi> Encode for Order<BigUint> {
-    fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
+impl<BigUint: BigUintApi> LegacyOrder<BigUint> {
+    // The same order in the current layout: a plain limit order without a broker, for sub-account 0. The old
+    // signature is dropped, it does not verify against the new hash anyway.
+    pub fn into_order(self) -> Order<BigUint> {
+        Order {
+            sender_address: self.sender_address.clone(),
+            matcher_address: self.matcher_address,
+            base_asset: self.base_asset,
+            quote_asset: self.quote_asset,
+            matcher_fee_asset: self.matcher_fee_asset,
+            amount: self.amount,
+            price: self.price,
+            matcher_fee: self.matcher_fee,
+            nonce: self.nonce,
+            expiration: self.expiration,
+            side: self.side,
+            trigger_price: BigUint::zero(),
+            trigger_direction: TriggerDirection::Above,
+            broker_address: Address::zero(),
+            sub_account: 0,
+            signer_address: self.sender_address,
+            signature: Vec::new(),
+        }
+    }
+}
+
+impl<BigUint: BigUintApi> Order<BigUint> {
+    fn dep_encode_unsigned<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
         self.sender_address.dep_encode_to(dest)?;
         self.matcher_address.dep_encode_to(dest)?;
         self.base_asset.dep_encode_to(dest)?;
@@ -70,35 +109,68 @@ impl<BigUint: BigUintApi> Encode for Order<BigUint> {
         self.nonce.dep_encode_to(dest)?;
         self.expiration.dep_encode_to(dest)?;
         self.side.dep_encode_to(dest)?;
-        self.signature.dep_encode_to(dest)
+        self.trigger_price.dep_encode_to(dest)?;
+        self.trigger_direction.dep_encode_to(dest)?;
+        self.broker_address.dep_encode_to(dest)?;
+        self.sub_account.dep_encode_to(dest)
     }
-}
 
-impl<BigUint: BigUintApi> Decode for Order<BigUint> {
-    fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
-        Result::Ok(Order {
-            sender_address: Address::dep_decode(input)?,
-            matcher_address: Address::dep_decode(input)?,
-            base_asset: Address::dep_decode(input)?,
-            quote_asset: Address::dep_decode(input)?,
-            matcher_fee_asset: Address::dep_decode(input)?,
-            amount: BigUint::dep_decode(input)?,
-            price: BigUint::dep_decode(input)?,
-            matcher_fee: BigUint::dep_decode(input)?,
-            nonce: BigUint::dep_decode(input)?,
-            expiration: u64::dep_decode(input)?,
-            side: OrderSide::dep_decode(input)?,
-            signature: H256::dep_decode(input)?,
-        })
+    pub fn signed_payload(&self, exchange_address: &Address) -> Result<Vec<u8>, EncodeError> {
+        let mut payload = Vec::new();
+        exchange_address.dep_encode_to(&mut payload)?;
+        ORDER_TAG.dep_encode_to(&mut payload)?;
+        self.dep_encode_unsigned(&mut payload)?;
+        Result::Ok(payload)
     }
-}
 
-impl<BigUint: BigUintApi> Order<BigUint> {
-    pub fn validate(&self) -> SCResult<()> {
-        // TODO: Actually validate order signatures
+    // The order must be signed by its sender, or by an active delegate of the sender allowed to trade.
+    // `order_hash` is the keccak256 of the signed payload.
+    pub fn validate(
+        &self,
+        order_hash: &H256,
+        is_signed_by: &dyn Fn(&Address, &H256, &[u8]) -> bool,
+        can_trade_for: &dyn Fn(&Address, &Address) -> bool,
+    ) -> SCResult<()> {
+        require!(
+            self.signer_address == self.sender_address
+                || can_trade_for(&self.sender_address, &self.signer_address),
+            ErrorCode::UnauthorizedSigner
+        );
+        require!(
+            is_signed_by(&self.signer_address, order_hash, self.signature.as_slice()),
+            ErrorCode::InvalidSignature
+        );
         Ok(())
     }
 
+    // Whether the order can be filled at the given last trade price of its pair.
+    // Orders without a trigger price always can, conditional ones never trigger before the first trade.
+    pub fn is_triggered(&self, last_price: &BigUint) -> bool {
+        if self.trigger_price == 0 {
+            return true;
+        }
+        if *last_price == 0 {
+            return false;
+        }
+
+        match self.trigger_direction {
+            TriggerDirection::Above => *last_price >= self.trigger_price,
+            TriggerDirection::Below => *last_price <= self.trigger_price,
+        }
+    }
+
+    // Returns the orders as (buy_order, sell_order) whichever way round they were passed
+    pub fn into_buy_and_sell(
+        first_order: Order<BigUint>,
+        second_order: Order<BigUint>,
+    ) -> SCResult<(Order<BigUint>, Order<BigUint>)> {
+        match (&first_order.side, &second_order.side) {
+            (OrderSide::Buy, OrderSide::Sell) => Ok((first_order, second_order)),
+            (OrderSide::Sell, OrderSide::Buy) => Ok((second_order, first_order)),
+            _ => sc_error!(ErrorCode::SideMismatch.error_message()),
+        }
+    }
+
     pub fn check_orders_info(
         buy_order: &Order<BigUint>,
         sell_order: &Order<BigUint>,
@@ -106,32 +178,37 @@ impl<BigUint: BigUintApi> Order<BigUint> {
         filled_amount: BigUint,
         filled_price: BigUint,
         current_time: u64,
+        is_tradable: &dyn Fn(&Address) -> bool,
     ) -> SCResult<()> {
-        sc_try!(buy_order.validate());
-        sc_try!(sell_order.validate());
+        require!(buy_order.side == OrderSide::Buy, ErrorCode::SideMismatch);
+        require!(sell_order.side == OrderSide::Sell, ErrorCode::SideMismatch);
 
-        require!(&buy_order.matcher_address == sender, INVALID_ORDER);
-        require!(&sell_order.matcher_address == sender, INVALID_ORDER);
+        require!(&buy_order.matcher_address == sender, ErrorCode::WrongMatcher);
+        require!(&sell_order.matcher_address == sender, ErrorCode::WrongMatcher);
 
-        require!(buy_order.base_asset == sell_order.base_asset, INVALID_ORDER);
+        require!(buy_order.base_asset == sell_order.base_asset, ErrorCode::AssetMismatch);
         require!(
             buy_order.quote_asset == sell_order.quote_asset,
-            INVALID_ORDER
+            ErrorCode::AssetMismatch
         );
+        require!(is_tradable(&buy_order.base_asset), ErrorCode::AssetNotTradable);
+        require!(is_tradable(&buy_order.quote_asset), ErrorCode::AssetNotTradable);
+        require!(is_tradable(&buy_order.matcher_fee_asset), ErrorCode::AssetNotTradable);
+        require!(is_tradable(&sell_order.matcher_fee_asset), ErrorCode::AssetNotTradable);
 
-        require!(filled_amount <= buy_order.amount, INVALID_ORDER);
-        require!(filled_amount <= sell_order.amount, INVALID_ORDER);
+        require_le!(filled_amount, buy_order.amount, ErrorCode::Overfill);
+        require_le!(filled_amount, sell_order.amount, ErrorCode::Overfill);
 
-        require!(filled_price <= buy_order.price, INVALID_ORDER);
-        require!(filled_price >= sell_order.price, INVALID_ORDER);
+        require_le!(filled_price, buy_order.price, ErrorCode::PriceOutOfRange);
+        require_le!(sell_order.price, filled_price, ErrorCode::PriceOutOfRange);
 
         require!(
             buy_order.expiration >= current_time,
-            ORDER_CANCELLED_OR_EXPIRED
+            ErrorCode::OrderExpired
         );
         require!(
             sell_order.expiration >= current_time,
-            ORDER_CANCELLED_OR_EXPIRED
+            ErrorCode::OrderExpired
         );
 
         Ok(())
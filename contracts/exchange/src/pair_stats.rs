@@ -0,0 +1,119 @@
+use codec_derive::{Decode, Encode};
+
+imports!();
+
+// Trades are aggregated into hourly buckets, the last 24 of which make up the rolling 24h stats
+static STATS_BUCKET_DURATION: u64 = 3600;
+static STATS_BUCKET_COUNT: u64 = 24;
+
+#[derive(Clone, Encode, Decode)]
+pub struct PairStatsBucket<BigUint: BigUintApi> {
+    pub hour: u64,
+    pub open: BigUint,
+    pub high: BigUint,
+    pub low: BigUint,
+    pub close: BigUint,
+    pub base_volume: BigUint,
+    pub quote_volume: BigUint,
+    pub trade_count: u64,
+}
+
+impl<BigUint: BigUintApi> PairStatsBucket<BigUint> {
+    pub fn new(hour: u64, price: &BigUint, base_amount: &BigUint, quote_amount: &BigUint) -> Self {
+        Self {
+            hour,
+            open: price.clone(),
+            high: price.clone(),
+            low: price.clone(),
+            close: price.clone(),
+            base_volume: base_amount.clone(),
+            quote_volume: quote_amount.clone(),
+            trade_count: 1,
+        }
+    }
+
+    pub fn add_trade(&mut self, price: &BigUint, base_amount: &BigUint, quote_amount: &BigUint) {
+        if *price > self.high {
+            self.high = price.clone();
+        }
+        if *price < self.low {
+            self.low = price.clone();
+        }
+        self.close = price.clone();
+        self.base_volume += base_amount;
+        self.quote_volume += quote_amount;
+        self.trade_count += 1;
+    }
+}
+
+// Adds a trade to the buckets of a pair, which are kept in chronological order. Buckets that have
+// fallen out of the 24h window are dropped so the list never grows past STATS_BUCKET_COUNT.
+pub fn record_trade<BigUint: BigUintApi>(
+    buckets: &mut Vec<PairStatsBucket<BigUint>>,
+    timestamp: u64,
+    price: &BigUint,
+    base_amount: &BigUint,
+    quote_amount: &BigUint,
+) {
+    let hour = timestamp / STATS_BUCKET_DURATION;
+    buckets.retain(|bucket| bucket.hour + STATS_BUCKET_COUNT > hour);
+
+    match buckets.last_mut() {
+        Some(bucket) if bucket.hour == hour => bucket.add_trade(price, base_amount, quote_amount),
+        _ => buckets.push(PairStatsBucket::new(hour, price, base_amount, quote_amount)),
+    }
+}
+
+/**
+ * Rolling 24h market data for a pair. The window moves an hour at a time, so it covers between 23
+ * and 24 hours of trades. All values are zero if the pair has not traded in that time.
+ */
+#[derive(Encode, Decode)]
+pub struct PairStats<BigUint: BigUintApi> {
+    pub open: BigUint,
+    pub high: BigUint,
+    pub low: BigUint,
+    pub close: BigUint,
+    pub base_volume: BigUint,
+    pub quote_volume: BigUint,
+    pub vwap: BigUint,
+    pub trade_count: u64,
+}
+
+impl<BigUint: BigUintApi> PairStats<BigUint> {
+    pub fn from_buckets(buckets: &[PairStatsBucket<BigUint>], timestamp: u64) -> Self {
+        let hour = timestamp / STATS_BUCKET_DURATION;
+        let mut stats = PairStats {
+            open: BigUint::zero(),
+            high: BigUint::zero(),
+            low: BigUint::zero(),
+            close: BigUint::zero(),
+            base_volume: BigUint::zero(),
+            quote_volume: BigUint::zero(),
+            vwap: BigUint::zero(),
+            trade_count: 0,
+        };
+
+        for bucket in buckets.iter().filter(|bucket| bucket.hour + STATS_BUCKET_COUNT > hour) {
+            if stats.trade_count == 0 {
+                stats.open = bucket.open.clone();
+                stats.low = bucket.low.clone();
+            }
+            if bucket.high > stats.high {
+                stats.high = bucket.high.clone();
+            }
+            if bucket.low < stats.low {
+                stats.low = bucket.low.clone();
+            }
+            stats.close = bucket.close.clone();
+            stats.base_volume += &bucket.base_volume;
+            stats.quote_volume += &bucket.quote_volume;
+            stats.trade_count += bucket.trade_count;
+        }
+
+        if stats.base_volume > 0 {
+            stats.vwap = stats.quote_volume.clone() / stats.base_volume.clone();
+        }
+        stats
+    }
+}
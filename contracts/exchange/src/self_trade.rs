@@ -0,0 +1,11 @@
+use codec_derive::{Decode, Encode};
+
+/**
+ * What fillOrders does when both orders have the same sender
+ */
+#[derive(Clone, PartialEq, Encode, Decode)]
+pub enum SelfTradePrevention {
+    Reject,
+    CancelOlder,
+    CancelNewer,
+}
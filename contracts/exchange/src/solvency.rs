@@ -0,0 +1,44 @@
+use codec_derive::{Decode, Encode};
+
+imports!();
+
+/**
+ * What the exchange holds of an asset against what it owes its users. The user balances include everything
+ * users can get back: their balances, stakes, queued withdrawals and broker fees. Liabilities are what margin
+ * users owe, so the holdings must cover at least `total_user_balances - total_liabilities`.
+ *
+ * ERD holdings are read when the view is called. Token holdings are those reported by the token contract
+ * at `holdings_timestamp`, the last time the exchange was found solvent, plus the deposits and minus the
+ * withdrawals and rescues since, which never counts tokens the exchange doesn't hold.
+ */
+#[derive(Encode, Decode)]
+pub struct Solvency<BigUint: BigUintApi> {
+    pub holdings: BigUint,
+    pub holdings_timestamp: u64,
+    pub total_user_balances: BigUint,
+    pub total_liabilities: BigUint,
+}
+
+impl<BigUint: BigUintApi> Solvency<BigUint> {
+    pub fn is_solvent(&self) -> bool {
+        self.holdings.clone() + self.total_liabilities.clone() >= self.total_user_balances
+    }
+
+    // What the exchange holds beyond what it owes, like tokens sent to it without a deposit. Never more than
+    // the holdings, the liabilities are owed to the exchange but not held by it.
+    pub fn excess(&self) -> BigUint {
+        saturating_sub(
+            &self.holdings,
+            &saturating_sub(&self.total_user_balances, &self.total_liabilities),
+        )
+    }
+}
+
+// Totals from before they were seeded can be smaller than what is taken off them, they stop at zero
+pub fn saturating_sub<BigUint: BigUintApi>(left: &BigUint, right: &BigUint) -> BigUint {
+    if left > right {
+        left.clone() - right.clone()
+    } else {
+        BigUint::zero()
+    }
+}
@@ -0,0 +1,22 @@
+use codec_derive::{Decode, Encode};
+
+imports!();
+
+/**
+ * Users with at least `min_stake` staked get `discount` basis points off the matcher fee of their orders
+ */
+#[derive(Clone, Encode, Decode)]
+pub struct FeeTier<BigUint: BigUintApi> {
+    pub min_stake: BigUint,
+    pub discount: u64,
+}
+
+// Discount of the highest tier the stake reaches, tiers are sorted by ascending `min_stake`
+pub fn fee_discount<BigUint: BigUintApi>(tiers: &[FeeTier<BigUint>], stake: &BigUint) -> u64 {
+    tiers
+        .iter()
+        .rev()
+        .find(|tier| tier.min_stake <= *stake)
+        .map(|tier| tier.discount)
+        .unwrap_or(0)
+}
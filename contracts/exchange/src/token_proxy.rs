@@ -22,4 +22,21 @@ pub trait TransferFrom {
         to: &Address,
         token_amount: BigUint,
     );
+
+    #[callback(token_holdings_callback)]
+    fn balanceOf(&self, #[callback_arg] cb_asset_address: &Address, address: &Address);
+}
+
+// Transfers of tokens that belong to the exchange rather than to a user, see rescueExcess
+#[elrond_wasm_derive::callable(TokenRescueProxy)]
+pub trait TokenRescue {
+    #[callback(excess_rescue_callback)]
+    fn transfer(
+        &self,
+        #[callback_arg] cb_asset_address: &Address,
+        #[callback_arg] cb_to: &Address,
+        #[callback_arg] cb_amount: BigUint,
+        to: &Address,
+        token_amount: BigUint,
+    );
 }
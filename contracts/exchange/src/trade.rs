@@ -1,10 +1,11 @@
-use elrond_codec::*;
+use codec_derive::{Decode, Encode};
 use elrond_wasm::BigUintApi;
 
 /**
  * Because the BigUint type isn't actually provided at compile time this is the only way we can
  * use it in structs. Using type params allows the actual implementation to be provided later.
  */
+#[derive(Encode, Decode)]
 pub struct Trade<BigUint: BigUintApi> {
     pub filled_price: BigUint,
     pub filled_amount: BigUint,
@@ -27,27 +28,3 @@ impl<BigUint: elrond_wasm::BigUintApi> Trade<BigUint> {
         }
     }
 }
-
-// this serialization method taken from
-// https://github.com/ElrondNetwork/sc-examples-rs/blob/master/features/src/ser_ex1.rs
-// Surely this can be implemented as a derive macro
-
-impl<BigUint: BigUintApi> Encode for Trade<BigUint> {
-    fn dep_encode_to<O: Output>(&self, dest: &mut O) -> Result<(), EncodeError> {
-        self.filled_price.dep_encode_to(dest)?;
-        self.filled_amount.dep_encode_to(dest)?;
-        self.fee_paid.dep_encode_to(dest)?;
-        self.timestamp.dep_encode_to(dest)
-    }
-}
-
-impl<BigUint: BigUintApi> Decode for Trade<BigUint> {
-    fn dep_decode<I: Input>(input: &mut I) -> Result<Self, DecodeError> {
-        Ok(Trade {
-            filled_price: BigUint::dep_decode(input)?,
-            filled_amount: BigUint::dep_decode(input)?,
-            fee_paid: BigUint::dep_decode(input)?,
-            timestamp: u64::dep_decode(input)?,
-        })
-    }
-}
@@ -0,0 +1,45 @@
+use codec_derive::{Decode, Encode};
+
+imports!();
+
+/**
+ * Snapshot of a pair's cumulative price, the sum of price * seconds over the pair's lifetime.
+ * The time-weighted average price between two observations is the difference of their
+ * cumulative prices divided by the time between them.
+ */
+#[derive(Clone, Encode, Decode)]
+pub struct Observation<BigUint: BigUintApi> {
+    pub timestamp: u64,
+    pub price_cumulative: BigUint,
+}
+
+// Appends an observation, keeping at most one per timestamp and dropping the oldest ones beyond `capacity`
+pub fn record_observation<BigUint: BigUintApi>(
+    observations: &mut Vec<Observation<BigUint>>,
+    capacity: u64,
+    timestamp: u64,
+    price_cumulative: &BigUint,
+) {
+    match observations.last() {
+        Some(last) if last.timestamp >= timestamp => {}
+        _ => observations.push(Observation {
+            timestamp,
+            price_cumulative: price_cumulative.clone(),
+        }),
+    }
+
+    let excess = observations.len().saturating_sub(capacity as usize);
+    observations.drain(..excess);
+}
+
+// The newest observation at least `window` seconds older than `timestamp`
+pub fn observation_before<BigUint: BigUintApi>(
+    observations: &[Observation<BigUint>],
+    timestamp: u64,
+    window: u64,
+) -> Option<&Observation<BigUint>> {
+    observations
+        .iter()
+        .rev()
+        .find(|observation| observation.timestamp + window <= timestamp)
+}
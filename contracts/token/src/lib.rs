@@ -5,7 +5,7 @@
 
 imports!();
 
-use common::require;
+use common::{checked_sub, ensure_caller, ErrorCode};
 
 #[elrond_wasm_derive::contract(TokenImpl)]
 pub trait Token {
@@ -48,8 +48,7 @@ pub trait Token {
         let caller = self.get_caller();
         let mut allowance = self.get_mut_allowance(sender, &caller);
         // require!(*amount > 0, "Zero value transfers not allowed");
-        require!(amount <= &*allowance, "allowance exceeded");
-        *allowance -= amount; // saved automatically at the end of scope
+        *allowance = sc_try!(checked_sub!(*allowance, *amount, "allowance exceeded")); // saved automatically at the end of scope
         self.perform_transfer(sender, recipient, amount)
     }
 
@@ -76,8 +75,7 @@ pub trait Token {
     fn perform_transfer(&self, sender: &Address, recipient: &Address, amount: &BigUint) -> SCResult<()> {        
         {
             let mut sender_balance = self.get_mut_balance(sender);
-            require!(amount <= &*sender_balance, "insufficient funds");
-            *sender_balance -= amount;
+            *sender_balance = sc_try!(checked_sub!(*sender_balance, *amount, "insufficient funds"));
         }
         {
             let mut recipient_balance = self.get_mut_balance(&recipient);
@@ -88,7 +86,7 @@ pub trait Token {
     }
 
     fn abort_if_owner_not_caller(&self) -> SCResult<()> {
-        require!(self.get_caller() == self.get_owner(), "Must be called by owner");
+        ensure_caller!(self, self.get_owner(), ErrorCode::NotOwner);
         Ok(())
     }
 